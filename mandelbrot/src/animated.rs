@@ -0,0 +1,403 @@
+use crate::pngstream::{write_chunk, write_header};
+use color_quant::NeuQuant;
+use gif::SetParameter;
+use image::ColorType;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::Path;
+
+/// How thoroughly NeuQuant samples each frame when choosing its GIF palette: it looks at
+/// one pixel in this many. Ten is the usual balance between speed and quality.
+const QUANTIZER_SAMPLING: i32 = 10;
+
+/// A file format that holds a whole animation in one file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Gif,
+    Apng,
+}
+
+impl Format {
+    /// The animation format the extension of `filename` calls for, if any.
+    pub fn from_filename(filename: &str) -> Option<Format> {
+        let extension = Path::new(filename).extension()?.to_str()?;
+        match extension.to_ascii_lowercase().as_str() {
+            "gif" => Some(Format::Gif),
+            "apng" => Some(Format::Apng),
+            _ => None,
+        }
+    }
+}
+
+#[test]
+fn test_format_from_filename() {
+    assert_eq!(Format::from_filename("zoom.gif"), Some(Format::Gif));
+    assert_eq!(Format::from_filename("out/Zoom.APNG"), Some(Format::Apng));
+    assert_eq!(Format::from_filename("zoom.png"), None);
+    assert_eq!(Format::from_filename("gif"), None);
+}
+
+/// How an animation plays back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Playback {
+    /// How long each frame is shown, in milliseconds.
+    pub delay: u16,
+    /// How many times the animation plays, or zero to loop forever.
+    pub loops: u16,
+}
+
+/// Writes the frames of an animation to `W` one at a time, as an animated GIF or APNG.
+pub enum AnimationEncoder<W: Write> {
+    Gif {
+        encoder: gif::Encoder<W>,
+        size: (u16, u16),
+        /// The delay in GIF's units of 10 ms.
+        delay: u16,
+        channels: usize,
+    },
+    Apng(ApngEncoder<W>),
+}
+
+impl<W: Write> AnimationEncoder<W> {
+    /// Start an animation of `frames` frames, each an image of the given `bounds` and
+    /// `color_type`.
+    pub fn new(
+        output: W,
+        format: Format,
+        bounds: (usize, usize),
+        color_type: ColorType,
+        frames: usize,
+        playback: Playback,
+    ) -> Result<AnimationEncoder<W>, io::Error> {
+        match format {
+            Format::Gif => {
+                let too_big =
+                    || io::Error::new(io::ErrorKind::InvalidInput, "image too large for GIF");
+                let width = u16::try_from(bounds.0).map_err(|_| too_big())?;
+                let height = u16::try_from(bounds.1).map_err(|_| too_big())?;
+                let mut encoder = gif::Encoder::new(output, width, height, &[])?;
+                encoder.set(match playback.loops {
+                    0 => gif::Repeat::Infinite,
+                    n => gif::Repeat::Finite(n),
+                })?;
+                Ok(AnimationEncoder::Gif {
+                    encoder,
+                    size: (width, height),
+                    delay: ((u32::from(playback.delay) + 5) / 10) as u16,
+                    channels: channels(color_type),
+                })
+            }
+            Format::Apng => Ok(AnimationEncoder::Apng(ApngEncoder::new(
+                output, bounds, color_type, frames, playback,
+            )?)),
+        }
+    }
+
+    /// Add the next frame, whose pixels are laid out as for `write_image`.
+    pub fn write_frame(&mut self, pixels: &[u8]) -> Result<(), io::Error> {
+        match self {
+            AnimationEncoder::Gif {
+                encoder,
+                size: (width, height),
+                delay,
+                channels,
+            } => {
+                let (palette, indices, transparent) = quantize(pixels, *channels);
+                let frame = gif::Frame {
+                    delay: *delay,
+                    transparent,
+                    width: *width,
+                    height: *height,
+                    palette: Some(palette),
+                    buffer: indices.into(),
+                    ..gif::Frame::default()
+                };
+                encoder.write_frame(&frame)
+            }
+            AnimationEncoder::Apng(encoder) => encoder.write_frame(pixels),
+        }
+    }
+
+    /// Write the end of the animation.
+    pub fn finish(self) -> Result<(), io::Error> {
+        match self {
+            // The GIF encoder writes its trailer when dropped.
+            AnimationEncoder::Gif { .. } => Ok(()),
+            AnimationEncoder::Apng(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// The number of bytes per pixel of `color_type`.
+fn channels(color_type: ColorType) -> usize {
+    match color_type {
+        ColorType::Gray(8) => 1,
+        ColorType::RGB(8) => 3,
+        ColorType::RGBA(8) => 4,
+        other => panic!("unsupported color type {:?}", other),
+    }
+}
+
+/// Reduce `pixels`, of `channels` bytes each, to at most 256 colors for GIF. Return the
+/// palette as RGB triples, each pixel's index into it, and the index standing for
+/// transparent pixels, if there are any.
+///
+/// Grayscale images fit a palette of every gray exactly, as do color images with few
+/// enough distinct colors, such as banded renders. Other color images are quantized with
+/// NeuQuant. GIF transparency is all or nothing, so pixels more than half transparent
+/// become fully transparent, and the rest opaque.
+fn quantize(pixels: &[u8], channels: usize) -> (Vec<u8>, Vec<u8>, Option<u8>) {
+    if channels == 1 {
+        let palette = (0..=255).flat_map(|v| [v, v, v]).collect();
+        return (palette, pixels.to_vec(), None);
+    }
+    let opaque = |pixel: &[u8]| channels == 3 || pixel[3] >= 128;
+    let colors: Vec<u8> = pixels
+        .chunks(channels)
+        .filter(|pixel| opaque(pixel))
+        .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+        .collect();
+    let has_transparent = colors.len() / 4 < pixels.len() / channels;
+    let capacity = if has_transparent { 255 } else { 256 };
+
+    let mut exact = HashMap::new();
+    for color in colors.chunks(4) {
+        let next = exact.len();
+        exact.entry([color[0], color[1], color[2]]).or_insert(next);
+        if exact.len() > capacity {
+            break;
+        }
+    }
+    let quantizer = if exact.len() > capacity {
+        Some(NeuQuant::new(QUANTIZER_SAMPLING, capacity, &colors))
+    } else {
+        None
+    };
+    let mut palette = match &quantizer {
+        Some(quantizer) => quantizer.color_map_rgb(),
+        None => {
+            let mut palette = vec![0; exact.len() * 3];
+            for (color, &index) in &exact {
+                palette[index * 3..][..3].copy_from_slice(color);
+            }
+            palette
+        }
+    };
+    let transparent = if has_transparent {
+        palette.extend([0, 0, 0]);
+        Some((palette.len() / 3 - 1) as u8)
+    } else {
+        None
+    };
+    let indices = pixels
+        .chunks(channels)
+        .map(|pixel| {
+            if !opaque(pixel) {
+                return transparent.unwrap();
+            }
+            let index = match &quantizer {
+                Some(quantizer) => quantizer.index_of(&[pixel[0], pixel[1], pixel[2], 255]),
+                None => exact[&[pixel[0], pixel[1], pixel[2]]],
+            };
+            index as u8
+        })
+        .collect();
+    (palette, indices, transparent)
+}
+
+#[test]
+fn test_quantize() {
+    let gray = [0, 17, 255];
+    assert_eq!(quantize(&gray, 1).1, gray);
+
+    // Few enough colors that every one gets a palette entry of its own.
+    let rgb = [255, 0, 0, 0, 0, 255, 255, 0, 0, 10, 200, 10];
+    let (palette, indices, transparent) = quantize(&rgb, 3);
+    assert_eq!(transparent, None);
+    assert_eq!(palette.len(), 9);
+    for (pixel, &index) in rgb.chunks(3).zip(&indices) {
+        assert_eq!(&palette[index as usize * 3..][..3], pixel);
+    }
+
+    // Too many colors: NeuQuant finds close ones, on average.
+    let rgb: Vec<u8> = (0..64 * 64)
+        .flat_map(|i| [(i % 64 * 4) as u8, (i / 64 * 4) as u8, 128])
+        .collect();
+    let (palette, indices, _) = quantize(&rgb, 3);
+    assert!(palette.len() <= 256 * 3);
+    let error: i32 = rgb
+        .chunks(3)
+        .zip(&indices)
+        .flat_map(|(pixel, &index)| {
+            let entry = &palette[index as usize * 3..][..3];
+            pixel
+                .iter()
+                .zip(entry)
+                .map(|(a, b)| (*a as i32 - *b as i32).abs())
+        })
+        .sum();
+    assert!(error / (rgb.len() as i32) < 8, "{}", error);
+
+    let rgba = [255, 0, 0, 255, 0, 0, 255, 10, 0, 0, 255, 200];
+    let (palette, indices, transparent) = quantize(&rgba, 4);
+    assert_eq!(transparent, Some((palette.len() / 3 - 1) as u8));
+    assert_eq!(indices[1], transparent.unwrap());
+    assert_ne!(indices[2], transparent.unwrap());
+    assert_eq!(quantize(&[0, 0, 0, 0], 4).1, [0]);
+}
+
+/// Writes an animated PNG. The PNG crate we use predates APNG, so this writes the chunks
+/// itself: an ordinary PNG whose image is the first frame, with an animation control
+/// chunk up front and each later frame in a frame data chunk, which viewers that don't
+/// understand APNG skip.
+pub struct ApngEncoder<W: Write> {
+    output: W,
+    bounds: (usize, usize),
+    /// The same bounds, as the chunks store them.
+    size: (u32, u32),
+    channels: usize,
+    delay: u16,
+    /// The sequence number of the next animation chunk.
+    sequence: u32,
+}
+
+impl<W: Write> ApngEncoder<W> {
+    fn new(
+        mut output: W,
+        bounds: (usize, usize),
+        color_type: ColorType,
+        frames: usize,
+        playback: Playback,
+    ) -> Result<ApngEncoder<W>, io::Error> {
+        let too_big = || io::Error::new(io::ErrorKind::InvalidInput, "image too large for APNG");
+        let size = (
+            u32::try_from(bounds.0).map_err(|_| too_big())?,
+            u32::try_from(bounds.1).map_err(|_| too_big())?,
+        );
+        let frames = u32::try_from(frames).map_err(|_| too_big())?;
+        write_header(&mut output, bounds, color_type)?;
+        let mut control = Vec::new();
+        control.extend(frames.to_be_bytes());
+        control.extend((playback.loops as u32).to_be_bytes());
+        write_chunk(&mut output, b"acTL", &control)?;
+        Ok(ApngEncoder {
+            output,
+            bounds,
+            size,
+            channels: channels(color_type),
+            delay: playback.delay,
+            sequence: 0,
+        })
+    }
+
+    fn write_frame(&mut self, pixels: &[u8]) -> Result<(), io::Error> {
+        let mut control = Vec::new();
+        control.extend(self.sequence.to_be_bytes());
+        control.extend(self.size.0.to_be_bytes());
+        control.extend(self.size.1.to_be_bytes());
+        control.extend([0; 8]); // The frame's offset.
+        control.extend(self.delay.to_be_bytes());
+        control.extend(1000u16.to_be_bytes()); // The delay is in thousandths of a second.
+        control.extend([0, 0]); // Replace the whole image; don't blend.
+        write_chunk(&mut self.output, b"fcTL", &control)?;
+        let first = self.sequence == 0;
+        self.sequence += 1;
+
+        // Every row starts with its filter type; we use none.
+        let mut scanlines = Vec::with_capacity(pixels.len() + self.bounds.1);
+        for row in pixels.chunks(self.bounds.0 * self.channels) {
+            scanlines.push(0);
+            scanlines.extend_from_slice(row);
+        }
+        let compressed = deflate::deflate_bytes_zlib(&scanlines);
+        if first {
+            write_chunk(&mut self.output, b"IDAT", &compressed)
+        } else {
+            let mut data = self.sequence.to_be_bytes().to_vec();
+            data.extend(compressed);
+            self.sequence += 1;
+            write_chunk(&mut self.output, b"fdAT", &data)
+        }
+    }
+
+    fn finish(mut self) -> Result<(), io::Error> {
+        write_chunk(&mut self.output, b"IEND", &[])?;
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+fn encode(format: Format, color_type: ColorType, frames: &[Vec<u8>], delay: u16) -> Vec<u8> {
+    let mut data = Vec::new();
+    let playback = Playback { delay, loops: 0 };
+    let mut encoder = AnimationEncoder::new(
+        &mut data,
+        format,
+        (4, 3),
+        color_type,
+        frames.len(),
+        playback,
+    )
+    .unwrap();
+    for frame in frames {
+        encoder.write_frame(frame).unwrap();
+    }
+    encoder.finish().unwrap();
+    data
+}
+
+#[test]
+fn test_gif() {
+    let frames: Vec<Vec<u8>> = (0..3).map(|i| vec![i * 80; 12]).collect();
+    let data = encode(Format::Gif, ColorType::Gray(8), &frames, 50);
+    let mut decoder = gif::Decoder::new(&data[..]);
+    decoder.set(gif::ColorOutput::Indexed);
+    let mut reader = decoder.read_info().unwrap();
+    for frame in &frames {
+        let decoded = reader.read_next_frame().unwrap().unwrap();
+        assert_eq!((decoded.width, decoded.height, decoded.delay), (4, 3, 5));
+        assert_eq!(&decoded.buffer[..], &frame[..]);
+    }
+    assert!(reader.read_next_frame().unwrap().is_none());
+
+    // GIF delays are in hundredths of a second, so the longest delay rounds down to fit.
+    let data = encode(Format::Gif, ColorType::Gray(8), &frames[..1], 65535);
+    let mut reader = gif::Decoder::new(&data[..]).read_info().unwrap();
+    assert_eq!(reader.read_next_frame().unwrap().unwrap().delay, 6554);
+}
+
+#[test]
+fn test_apng() {
+    use image::ImageDecoder;
+
+    let frames: Vec<Vec<u8>> = (0..3).map(|i| vec![i * 80 + 1; 36]).collect();
+    let data = encode(Format::Apng, ColorType::RGB(8), &frames, 50);
+
+    // Walk the chunks, checking each one's checksum.
+    let mut kinds = Vec::new();
+    let mut rest = &data[8..];
+    while !rest.is_empty() {
+        let length = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+        let mut check = Vec::new();
+        write_chunk(
+            &mut check,
+            rest[4..8].try_into().unwrap(),
+            &rest[8..8 + length],
+        )
+        .unwrap();
+        assert_eq!(&check[..], &rest[..12 + length]);
+        kinds.push(String::from_utf8(rest[4..8].to_vec()).unwrap());
+        rest = &rest[12 + length..];
+    }
+    let expected = [
+        "IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "fcTL", "fdAT", "IEND",
+    ];
+    assert_eq!(kinds, expected);
+
+    // Viewers that know nothing of APNG see the first frame.
+    let mut decoder = image::png::PNGDecoder::new(&data[..]);
+    match decoder.read_image().unwrap() {
+        image::DecodingResult::U8(pixels) => assert_eq!(pixels, frames[0]),
+        other => panic!("unexpected {:?}", other),
+    }
+}
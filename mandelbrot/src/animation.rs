@@ -0,0 +1,256 @@
+use crate::animated::{AnimationEncoder, Format, Playback};
+use crate::fixed::{Fixed, FixedComplex};
+use crate::view::View;
+use crate::Arguments;
+use std::fs::{self, read_to_string, File};
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+/// A view the animation passes through at a given frame.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keyframe {
+    pub frame: usize,
+    pub center: FixedComplex,
+    /// The distance from the center to the nearest edge of the image.
+    pub radius: f64,
+    /// The view's rotation, in radians.
+    pub rotation: f64,
+}
+
+impl Keyframe {
+    /// The keyframe showing `view` at `frame`.
+    pub fn from_view(frame: usize, view: &View) -> Keyframe {
+        Keyframe {
+            frame,
+            center: view.center_exact(view.bits()),
+            radius: view.radius(),
+            rotation: view.rotation,
+        }
+    }
+}
+
+/// An animation: the keyframes it passes through, in order, and how many frames to render.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Animation {
+    pub keyframes: Vec<Keyframe>,
+    pub frames: usize,
+    /// Skip frames that were written by an earlier run.
+    pub resume: bool,
+    /// How to play the animation back, when writing it to a single GIF or APNG file.
+    pub playback: Playback,
+}
+
+/// Parse a keyframe file. Each line holds a frame number, a center point, a radius and an
+/// optional rotation in degrees, like `120 -0.745,0.113 1e-6 45`; lines starting with `//`
+/// are comments. Frame numbers must increase from one keyframe to the next.
+pub fn parse_keyframes(text: &str) -> Result<Vec<Keyframe>, String> {
+    let mut keyframes: Vec<Keyframe> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with("//") {
+            continue;
+        }
+        let error = |message: &str| format!("line {}: {}", number + 1, message);
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() != 3 && fields.len() != 4 {
+            return Err(error("expected FRAME RE,IM RADIUS [DEGREES]"));
+        }
+        let frame = fields[0]
+            .parse()
+            .map_err(|_| error(&format!("bad frame number '{}'", fields[0])))?;
+        if let Some(last) = keyframes.last() {
+            if frame <= last.frame {
+                return Err(error("frame numbers must increase"));
+            }
+        }
+        let center = crate::parse_fixed_complex(fields[1])
+            .ok_or_else(|| error(&format!("bad center '{}'", fields[1])))?;
+        let radius = match fields[2].parse::<f64>() {
+            Ok(radius) if radius > 0.0 && radius.is_finite() => radius,
+            _ => return Err(error(&format!("bad radius '{}'", fields[2]))),
+        };
+        let degrees = match fields.get(3) {
+            None => 0.0,
+            Some(field) => field
+                .parse::<f64>()
+                .map_err(|_| error(&format!("bad rotation '{}'", field)))?,
+        };
+        keyframes.push(Keyframe {
+            frame,
+            center,
+            radius,
+            rotation: degrees.to_radians(),
+        });
+    }
+    if keyframes.is_empty() {
+        return Err("keyframe file has no keyframes".to_string());
+    }
+    Ok(keyframes)
+}
+
+/// Read and parse a keyframe file.
+pub fn load_keyframes(filename: &str) -> Result<Vec<Keyframe>, io::Error> {
+    let text = read_to_string(filename)?;
+    parse_keyframes(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[test]
+fn test_parse_keyframes() {
+    let keyframes = parse_keyframes(
+        "// zoom in, turning halfway round\n\
+         0 -0.5,0 2\n\
+         \n\
+         100 -0.75,0.1 1e-3 180\n",
+    )
+    .unwrap();
+    assert_eq!(keyframes.len(), 2);
+    assert_eq!(keyframes[1].frame, 100);
+    assert_eq!(keyframes[1].radius, 1e-3);
+    assert_eq!(keyframes[1].rotation, std::f64::consts::PI);
+    assert_eq!(keyframes[0].rotation, 0.0);
+    assert!(parse_keyframes("").is_err());
+    assert!(parse_keyframes("0 -0.5,0").is_err());
+    assert!(parse_keyframes("0 -0.5,0 0").is_err());
+    assert!(parse_keyframes("5 0,0 1\n5 0,0 1").is_err());
+}
+
+/// The keyframe for `frame`, interpolated between the keyframes on either side of it.
+///
+/// The radius changes exponentially, so the zoom runs at a constant speed. The center
+/// moves in step with the radius, so the next keyframe's center drifts steadily toward
+/// the middle of the image as the view closes in on it, rather than arriving early.
+pub fn interpolate(keyframes: &[Keyframe], frame: usize) -> Keyframe {
+    let next = match keyframes.iter().position(|k| k.frame > frame) {
+        Some(0) => return keyframes[0].clone(),
+        Some(next) => next,
+        None => return keyframes[keyframes.len() - 1].clone(),
+    };
+    let (a, b) = (&keyframes[next - 1], &keyframes[next]);
+    let t = (frame - a.frame) as f64 / (b.frame - a.frame) as f64;
+    let radius = a.radius * (b.radius / a.radius).powf(t);
+    let progress = if (a.radius - b.radius).abs() > a.radius * 1e-9 {
+        (a.radius - radius) / (a.radius - b.radius)
+    } else {
+        t
+    };
+    let bits = a.center.re.bits().max(b.center.re.bits());
+    let (start, end) = (a.center.with_bits(bits), b.center.with_bits(bits));
+    let progress = Fixed::from_f64(progress, bits);
+    let center = FixedComplex {
+        re: &start.re + &(&(&end.re - &start.re) * &progress),
+        im: &start.im + &(&(&end.im - &start.im) * &progress),
+    };
+    Keyframe {
+        frame,
+        center,
+        radius,
+        rotation: a.rotation + (b.rotation - a.rotation) * t,
+    }
+}
+
+#[test]
+fn test_interpolate() {
+    let keyframes = parse_keyframes("10 0,0 1\n20 1,-1 0.01 90").unwrap();
+    assert_eq!(interpolate(&keyframes, 0), keyframes[0]);
+    assert_eq!(interpolate(&keyframes, 10), keyframes[0]);
+    assert_eq!(interpolate(&keyframes, 20), keyframes[1]);
+    assert_eq!(interpolate(&keyframes, 30).radius, 0.01);
+
+    // Halfway through, the zoom is halfway in logarithmic terms.
+    let middle = interpolate(&keyframes, 15);
+    assert!((middle.radius - 0.1).abs() < 1e-12);
+    assert!((middle.rotation - std::f64::consts::FRAC_PI_4).abs() < 1e-12);
+    // The center has covered as much of its way as the radius has: (1 - 0.1) / (1 - 0.01).
+    let center = middle.center.to_complex();
+    assert!((center.re - 0.9 / 0.99).abs() < 1e-12);
+    assert!((center.im + 0.9 / 0.99).abs() < 1e-12);
+
+    // Every frame zooms in by the same factor.
+    let radii: Vec<f64> = (10..=20)
+        .map(|f| interpolate(&keyframes, f).radius)
+        .collect();
+    for pair in radii.windows(2) {
+        assert!((pair[1] / pair[0] - 0.01f64.powf(0.1)).abs() < 1e-12);
+    }
+}
+
+/// The name of the file for `frame` of an animation `frames` long, numbering the given
+/// `filename`: `zoom.png` becomes `zoom-0000.png`, `zoom-0001.png` and so on.
+pub fn frame_filename(filename: &str, frame: usize, frames: usize) -> String {
+    let width = (frames.max(1) - 1).to_string().len().max(4);
+    let (stem, extension) = match filename.rfind('.') {
+        Some(dot) if !filename[dot..].contains('/') => filename.split_at(dot),
+        _ => (filename, ""),
+    };
+    format!("{}-{:0width$}{}", stem, frame, extension, width = width)
+}
+
+#[test]
+fn test_frame_filename() {
+    assert_eq!(frame_filename("zoom.png", 7, 100), "zoom-0007.png");
+    assert_eq!(
+        frame_filename("out/zoom.png", 12345, 20000),
+        "out/zoom-12345.png"
+    );
+    assert_eq!(frame_filename("out.d/zoom", 3, 10), "out.d/zoom-0003");
+}
+
+/// The view to render for `frame` of `animation`, for an image of the given `bounds`.
+fn frame_view(animation: &Animation, frame: usize, bounds: (usize, usize)) -> View {
+    let keyframe = interpolate(&animation.keyframes, frame);
+    View {
+        rotation: keyframe.rotation,
+        ..View::centered(&keyframe.center, keyframe.radius, bounds)
+    }
+}
+
+/// Render each frame of `animation` with the settings in `args`. If `args.filename` names
+/// a GIF or APNG file, the frames all go into that one file; otherwise each is written to
+/// a numbered file named after it.
+///
+/// Files are written under a temporary name and renamed once complete, so an interrupted
+/// run never leaves a partial frame behind for `resume` to mistake for a finished one.
+pub fn render_frames(args: &Arguments, animation: &Animation) -> Result<(), io::Error> {
+    if let Some(format) = Format::from_filename(&args.filename) {
+        return render_animation_file(args, animation, format);
+    }
+    for frame in 0..animation.frames {
+        let filename = frame_filename(&args.filename, frame, animation.frames);
+        if animation.resume && Path::new(&filename).exists() {
+            continue;
+        }
+        let view = frame_view(animation, frame, args.bounds);
+        let partial = format!("{}.partial", filename);
+        crate::write_output(args, &view, &partial)?;
+        fs::rename(&partial, &filename)?;
+        eprintln!("frame {} of {}: {}", frame + 1, animation.frames, filename);
+    }
+    Ok(())
+}
+
+/// Render each frame of `animation`, writing them all to the animated image file
+/// `args.filename`.
+fn render_animation_file(
+    args: &Arguments,
+    animation: &Animation,
+    format: Format,
+) -> Result<(), io::Error> {
+    let partial = format!("{}.partial", args.filename);
+    let mut output = BufWriter::new(File::create(&partial)?);
+    let mut encoder = AnimationEncoder::new(
+        &mut output,
+        format,
+        args.bounds,
+        args.coloring.palette.color_type(),
+        animation.frames,
+        animation.playback,
+    )?;
+    for frame in 0..animation.frames {
+        let view = frame_view(animation, frame, args.bounds);
+        encoder.write_frame(&crate::render_image(args, &view))?;
+        eprintln!("frame {} of {}", frame + 1, animation.frames);
+    }
+    encoder.finish()?;
+    output.into_inner().map_err(|e| e.into_error())?;
+    fs::rename(&partial, &args.filename)
+}
@@ -0,0 +1,309 @@
+use crate::view::View;
+use crate::{escape_time, Arguments, Fractal, Mode, Tracking};
+use crossbeam::channel;
+use image::ColorType;
+use num::Complex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::io;
+
+/// The seed `--seed` defaults to, so that the same command always draws the same image.
+pub const DEFAULT_SEED: u64 = 0;
+
+/// How many random points each unit of work draws. Each unit has its own random number
+/// generator, seeded from the seed and the unit's index, so the points drawn don't depend
+/// on which thread draws them.
+const CHUNK_SAMPLES: u64 = 1 << 14;
+
+/// Random points are drawn from the square reaching this far from the origin along each
+/// axis, which holds the whole of every set the formulas draw.
+const SAMPLE_RADIUS: f64 = 2.0;
+
+/// The fraction of lit pixels drawn at less than full brightness. The rest, the
+/// brightest few, are clipped, so that a handful of very busy pixels don't leave the rest
+/// of the image dark.
+const WHITE_POINT: f64 = 0.999;
+
+/// The settings for drawing a Buddhabrot: the orbits of many random points, with each
+/// pixel's brightness counting how many of them passed through it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Buddhabrot {
+    /// The number of random points to draw.
+    pub samples: u64,
+    /// Draw the orbits of the points that never escape, the anti-Buddhabrot, rather than
+    /// those that do.
+    pub anti: bool,
+    /// The iteration limit for each of the image's channels: one for a grayscale image, or
+    /// red, green and blue for a Nebulabrot.
+    pub limits: Vec<usize>,
+    pub seed: u64,
+}
+
+/// The pixel of an image of the given `bounds` showing `view` that `point` falls in, if
+/// any: the inverse of `pixel_to_point`. `unturn` is `view` turned back the other way.
+fn point_to_pixel(
+    bounds: (usize, usize),
+    point: Complex<f64>,
+    view: &View,
+    unturn: &View,
+) -> Option<usize> {
+    let point = unturn.rotate(point);
+    let (upper_left, lower_right) = (view.upper_left, view.lower_right);
+    let x = (point.re - upper_left.re) / (lower_right.re - upper_left.re) * bounds.0 as f64;
+    let y = (upper_left.im - point.im) / (upper_left.im - lower_right.im) * bounds.1 as f64;
+    if x >= 0.0 && y >= 0.0 && x < bounds.0 as f64 && y < bounds.1 as f64 {
+        Some(y as usize * bounds.0 + x as usize)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn test_point_to_pixel() {
+    let mut view = View::corners("-1,1", "1,-1").unwrap();
+    let bounds = (100, 50);
+    for rotation in [0.0, 0.7] {
+        view.rotation = rotation;
+        let unturn = View {
+            rotation: -rotation,
+            ..view.clone()
+        };
+        for (x, y) in [(0, 0), (10, 20), (99, 49)] {
+            // The middle of each pixel, turned along with the view.
+            let corner = crate::pixel_to_point(bounds, (x, y), view.upper_left, view.lower_right);
+            let middle = corner
+                + Complex {
+                    re: 0.01,
+                    im: -0.02,
+                };
+            let point = view.rotate(middle);
+            assert_eq!(
+                point_to_pixel(bounds, point, &view, &unturn),
+                Some(y * bounds.0 + x)
+            );
+        }
+        let outside = view.rotate(Complex { re: 1.5, im: 0.0 });
+        assert_eq!(point_to_pixel(bounds, outside, &view, &unturn), None);
+    }
+}
+
+/// Follow the orbit of `c` under `fractal`'s formula, and count each point of it that
+/// lands in the image in `hits`, which holds a run of one count per channel for each
+/// pixel. A channel counts the orbit if it escapes within that channel's limit, or for
+/// the anti-Buddhabrot, if it doesn't.
+fn trace(
+    fractal: &Fractal,
+    buddhabrot: &Buddhabrot,
+    c: Complex<f64>,
+    image: (&View, &View, (usize, usize)),
+    hits: &mut [u32],
+) {
+    let limit = *buddhabrot.limits.iter().max().unwrap();
+    let escape = if fractal.known_interior(c) {
+        None
+    } else {
+        escape_time(
+            &fractal.formula,
+            crate::ZERO,
+            c,
+            Tracking::NONE,
+            limit,
+            fractal.bailout,
+        )
+    };
+    // How many points of the orbit each channel counts.
+    let mut lengths = [0; 3];
+    for (length, &channel_limit) in lengths.iter_mut().zip(&buddhabrot.limits) {
+        *length = match (escape, buddhabrot.anti) {
+            (Some(escape), false) if escape.count < channel_limit => escape.count,
+            (Some(escape), true) if escape.count >= channel_limit => channel_limit,
+            (None, true) => channel_limit,
+            _ => 0,
+        };
+    }
+    let channels = buddhabrot.limits.len();
+    let longest = lengths.iter().copied().max().unwrap();
+    let (view, unturn, bounds) = image;
+    let mut z = crate::ZERO;
+    for i in 1..=longest {
+        z = fractal.formula.step(z, c);
+        if let Some(pixel) = point_to_pixel(bounds, z, view, unturn) {
+            for (k, &length) in lengths[..channels].iter().enumerate() {
+                if i <= length {
+                    let hit = &mut hits[pixel * channels + k];
+                    *hit = hit.saturating_add(1);
+                }
+            }
+        }
+    }
+}
+
+/// Draw `buddhabrot.samples` random points, trace their orbits under `fractal` onto an
+/// image of `view` with the given `bounds`, and return each pixel's hit counts, one per
+/// channel. The work is shared among `threads` threads, each adding into a buffer of its
+/// own; the result is the same however many there are.
+pub fn accumulate(
+    fractal: &Fractal,
+    buddhabrot: &Buddhabrot,
+    view: &View,
+    bounds: (usize, usize),
+    threads: usize,
+) -> Vec<u32> {
+    let unturn = View {
+        rotation: -view.rotation,
+        ..view.clone()
+    };
+    let size = bounds.0 * bounds.1 * buddhabrot.limits.len();
+    let chunks = buddhabrot.samples.div_ceil(CHUNK_SAMPLES);
+    // Hand out the chunks through a short queue, so as not to hold all their indices.
+    let (chunk_sender, chunk_receiver) = channel::bounded::<u64>(threads);
+    let buffers: Vec<Vec<u32>> = crossbeam::scope(|spawner| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                let (chunk_receiver, unturn) = (chunk_receiver.clone(), &unturn);
+                spawner.spawn(move |_| {
+                    let mut hits = vec![0; size];
+                    for chunk in chunk_receiver {
+                        let mut seed = [0; 32];
+                        seed[..8].copy_from_slice(&buddhabrot.seed.to_le_bytes());
+                        seed[8..16].copy_from_slice(&chunk.to_le_bytes());
+                        let mut rng = StdRng::from_seed(seed);
+                        let first = chunk * CHUNK_SAMPLES;
+                        for _ in first..buddhabrot.samples.min(first + CHUNK_SAMPLES) {
+                            let c = Complex {
+                                re: rng.gen_range(-SAMPLE_RADIUS..SAMPLE_RADIUS),
+                                im: rng.gen_range(-SAMPLE_RADIUS..SAMPLE_RADIUS),
+                            };
+                            trace(fractal, buddhabrot, c, (view, unturn, bounds), &mut hits);
+                        }
+                    }
+                    hits
+                })
+            })
+            .collect();
+        for chunk in 0..chunks {
+            chunk_sender.send(chunk).unwrap();
+        }
+        drop(chunk_sender);
+        workers.into_iter().map(|w| w.join().unwrap()).collect()
+    })
+    .unwrap();
+    let mut total = vec![0u32; size];
+    for buffer in buffers {
+        for (sum, hits) in total.iter_mut().zip(buffer) {
+            *sum = sum.saturating_add(hits);
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+fn sample_buddhabrot(anti: bool, limits: Vec<usize>) -> Buddhabrot {
+    Buddhabrot {
+        samples: 20000,
+        anti,
+        limits,
+        seed: 7,
+    }
+}
+
+#[test]
+fn test_accumulate() {
+    let fractal = Fractal::default();
+    let view = View::corners("-2,1.5", "1,-1.5").unwrap();
+    let bounds = (30, 30);
+    let buddhabrot = sample_buddhabrot(false, vec![200, 50, 20]);
+    let hits = accumulate(&fractal, &buddhabrot, &view, bounds, 1);
+    assert!(hits.iter().any(|&h| h > 0));
+    // The same seed gives the same image on any number of threads, and another seed
+    // a different one.
+    assert_eq!(accumulate(&fractal, &buddhabrot, &view, bounds, 3), hits);
+    let reseeded = Buddhabrot {
+        seed: 8,
+        ..buddhabrot.clone()
+    };
+    assert_ne!(accumulate(&fractal, &reseeded, &view, bounds, 1), hits);
+    // Every orbit escaping within a lower limit also escapes within a higher one, so the
+    // channels with higher limits see at least as many hits.
+    for pixel in hits.chunks(3) {
+        assert!(pixel[0] >= pixel[1] && pixel[1] >= pixel[2]);
+    }
+
+    // An orbit that never escapes stays within 2 of the origin, so the anti-Buddhabrot
+    // leaves the pixels entirely farther out than that dark.
+    let anti = sample_buddhabrot(true, vec![100]);
+    let hits = accumulate(&fractal, &anti, &view, bounds, 2);
+    for (i, &h) in hits.iter().enumerate() {
+        let pixel = (i % bounds.0, i / bounds.0);
+        let corner = crate::pixel_to_point(bounds, pixel, view.upper_left, view.lower_right);
+        let nearest = Complex {
+            re: corner.re.max(0.0).min(corner.re + 0.1),
+            im: (corner.im - 0.1).max(0.0).min(corner.im),
+        };
+        if nearest.norm() > 2.0 {
+            assert_eq!(h, 0);
+        }
+    }
+    assert!(hits.iter().any(|&h| h > 0));
+}
+
+/// Turn each channel's hit counts into brightnesses, rising evenly from black for no
+/// hits to white at the channel's white point.
+fn expose(hits: &[u32], channels: usize) -> Vec<u8> {
+    let mut pixels = vec![0; hits.len()];
+    for k in 0..channels {
+        let mut lit: Vec<u32> = hits[k..]
+            .iter()
+            .step_by(channels)
+            .copied()
+            .filter(|&h| h > 0)
+            .collect();
+        if lit.is_empty() {
+            continue;
+        }
+        lit.sort_unstable();
+        let white = lit[((lit.len() - 1) as f64 * WHITE_POINT) as usize] as f64;
+        for (pixel, &h) in pixels[k..]
+            .iter_mut()
+            .step_by(channels)
+            .zip(hits[k..].iter().step_by(channels))
+        {
+            *pixel = (255.0 * (h as f64 / white).min(1.0)).round() as u8;
+        }
+    }
+    pixels
+}
+
+#[test]
+fn test_expose() {
+    let mut hits: Vec<u32> = (0..2000).collect();
+    hits.push(1_000_000);
+    let pixels = expose(&hits, 1);
+    assert_eq!(pixels[0], 0);
+    assert_eq!(pixels[2000], 255);
+    // The one outlier is clipped rather than setting the scale for everything else.
+    assert_eq!(pixels[1000], 128);
+    // Channels are scaled separately.
+    let pixels = expose(&[0, 10, 5, 20], 2);
+    assert_eq!(pixels, [0, 255, 255, 255]);
+}
+
+/// Draw the Buddhabrot `buddhabrot` with the settings in `args`, and write it to
+/// `args.filename` as a PNG: grayscale, or RGB for a Nebulabrot.
+pub fn render(args: &Arguments, buddhabrot: &Buddhabrot) -> Result<(), io::Error> {
+    assert_eq!(args.fractal.mode, Mode::Mandelbrot);
+    let bounds = args.sample_bounds();
+    let hits = accumulate(&args.fractal, buddhabrot, &args.view, bounds, args.threads);
+    let channels = buddhabrot.limits.len();
+    let pixels = crate::supersample::downsample(
+        &expose(&hits, channels),
+        bounds,
+        channels,
+        args.supersample,
+    );
+    let color_type = match channels {
+        1 => ColorType::Gray(8),
+        _ => ColorType::RGB(8),
+    };
+    crate::write_image(&args.filename, &pixels, args.bounds, color_type)
+}
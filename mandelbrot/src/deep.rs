@@ -0,0 +1,232 @@
+use crate::fixed::{Fixed, FixedComplex};
+use crate::formula::Formula;
+use crate::tiles::{render_tiles, tiles, TILE_SIZE};
+use crate::view::View;
+use crate::{Escape, Fractal, Mode, Sample, Tracking, EXTRA_ITERATIONS};
+use num::Complex;
+
+/// When neighboring pixels are less than this far apart, relative to the size of the
+/// coordinates, f64 has only about a thousand distinct values per pixel, and rounding
+/// error in the orbit shows up as blocks.
+const F64_RESOLUTION: f64 = f64::EPSILON * 1024.0;
+
+/// Extra fraction bits carried beyond those needed to tell neighboring pixels apart,
+/// to absorb the rounding error that accumulates over the iterations.
+const GUARD_BITS: u32 = 32;
+
+/// The distance between neighboring pixels: the smaller of the horizontal and vertical
+/// spacing.
+pub fn pixel_spacing(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> f64 {
+    let width = (lower_right.re - upper_left.re).abs() / bounds.0 as f64;
+    let height = (upper_left.im - lower_right.im).abs() / bounds.1 as f64;
+    width.min(height)
+}
+
+/// Return true if pixels in this view are too close together to render with f64.
+pub fn needs_fixed(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> bool {
+    let magnitude = upper_left.norm().max(lower_right.norm()).max(1.0);
+    pixel_spacing(bounds, upper_left, lower_right) < magnitude * F64_RESOLUTION
+}
+
+#[test]
+fn test_needs_fixed() {
+    let corner = |re, im| Complex { re, im };
+    assert!(!needs_fixed(
+        (1000, 750),
+        corner(-1.20, 0.35),
+        corner(-1.0, 0.20)
+    ));
+    assert!(!needs_fixed(
+        (1000, 1000),
+        corner(-0.75, 0.1),
+        corner(-0.75 + 1e-9, 0.1 - 1e-9)
+    ));
+    assert!(needs_fixed(
+        (1000, 1000),
+        corner(-0.75, 0.1),
+        corner(-0.75 + 1e-14, 0.1 - 1e-14)
+    ));
+}
+
+/// The number of fraction bits needed to render this view without losing detail.
+pub fn precision(
+    bounds: (usize, usize),
+    upper_left: Complex<f64>,
+    lower_right: Complex<f64>,
+) -> u32 {
+    let spacing = pixel_spacing(bounds, upper_left, lower_right);
+    (-spacing.log2()).ceil().max(0.0) as u32 + GUARD_BITS
+}
+
+/// Like `escape_time`, but iterating in arbitrary precision. The derivative and the
+/// distance to a trap only need to be roughly right, so they are kept in f64.
+fn escape_time_fixed(
+    formula: &Formula,
+    mut z: FixedComplex,
+    c: &FixedComplex,
+    mut tracking: Tracking,
+    limit: usize,
+    bailout: f64,
+) -> Option<Escape> {
+    for i in 0..limit {
+        if z.norm_sqr() > bailout * bailout {
+            // Once the orbit has escaped, f64 is plenty for smooth coloring.
+            let c = c.to_complex();
+            let mut z = z.to_complex();
+            for _ in 0..EXTRA_ITERATIONS {
+                tracking.step(formula, z);
+                z = formula.step(z, c);
+            }
+            return Some(tracking.escape(i, z));
+        }
+        if tracking.derivative.is_some() {
+            tracking.step(formula, z.to_complex());
+        }
+        z = formula.step_fixed(&z, c);
+        if tracking.trap.is_some() {
+            tracking.visit(z.to_complex());
+        }
+    }
+    None
+}
+
+/// Like `pixel_to_point`, but in arbitrary precision.
+fn pixel_to_point_fixed(
+    bounds: (usize, usize),
+    pixel: (usize, usize),
+    upper_left: &FixedComplex,
+    lower_right: &FixedComplex,
+) -> FixedComplex {
+    let width = &lower_right.re - &upper_left.re;
+    let height = &upper_left.im - &lower_right.im;
+    FixedComplex {
+        re: &upper_left.re + &width.scale(pixel.0, bounds.0),
+        im: &upper_left.im - &height.scale(pixel.1, bounds.1),
+    }
+}
+
+#[test]
+fn test_pixel_to_point_fixed() {
+    let corner = |re: &str, im: &str| FixedComplex {
+        re: re.parse::<Fixed>().unwrap().with_bits(80),
+        im: im.parse::<Fixed>().unwrap().with_bits(80),
+    };
+    let point = pixel_to_point_fixed(
+        (100, 200),
+        (25, 175),
+        &corner("-1.0", "1.0"),
+        &corner("1.0", "-1.0"),
+    );
+    assert_eq!(
+        point.to_complex(),
+        Complex {
+            re: -0.5,
+            im: -0.75
+        }
+    );
+}
+
+/// Render `view` of `fractal` like `render`, but computing every orbit in arbitrary
+/// precision with the given number of fraction `bits`.
+pub fn render(
+    samples: &mut [Sample],
+    bounds: (usize, usize),
+    view: &View,
+    bits: u32,
+    fractal: &Fractal,
+    threads: usize,
+) {
+    assert!(fractal.formula.supports_fixed());
+    let upper_left = view.upper_left_exact.with_bits(bits);
+    let lower_right = view.lower_right_exact.with_bits(bits);
+    let center = view.center_exact(bits);
+    let spacing = pixel_spacing(bounds, view.upper_left, view.lower_right);
+    let tracking = fractal.tracking();
+    let julia_c = match fractal.mode {
+        Mode::Mandelbrot => None,
+        Mode::Julia(c) => Some(FixedComplex::from_complex(c, bits)),
+    };
+    let tiles = tiles(bounds, (TILE_SIZE, TILE_SIZE));
+    render_tiles(samples, bounds, &tiles, threads, |buffer, tile| {
+        for row in 0..tile.height {
+            for column in 0..tile.width {
+                let pixel = (tile.left + column, tile.top + row);
+                let point = pixel_to_point_fixed(bounds, pixel, &upper_left, &lower_right);
+                let point = view.rotate_exact(point, &center);
+                let escape = match &julia_c {
+                    None => escape_time_fixed(
+                        &fractal.formula,
+                        FixedComplex {
+                            re: Fixed::zero(bits),
+                            im: Fixed::zero(bits),
+                        },
+                        &point,
+                        tracking,
+                        fractal.limit,
+                        fractal.bailout,
+                    ),
+                    Some(c) => escape_time_fixed(
+                        &fractal.formula,
+                        point,
+                        c,
+                        tracking,
+                        fractal.limit,
+                        fractal.bailout,
+                    ),
+                };
+                buffer[row * tile.width + column] = fractal.sample(escape, spacing);
+            }
+        }
+    });
+}
+
+#[test]
+fn test_fixed_render_matches_f64() {
+    // Where f64 is precise enough, both paths should agree on nearly every pixel, and on
+    // the distance estimates and trap distances of those they agree on.
+    let bounds = (40, 30);
+    let tricorn = Fractal {
+        formula: Formula::Tricorn,
+        distance: true,
+        ..Fractal::default()
+    };
+    let trapped = Fractal {
+        trap: "circle:0.1,-0.2,0.5".parse().ok(),
+        ..Fractal::default()
+    };
+    let mut view = View::corners("-1.20,0.35", "-1.0,0.20").unwrap();
+    for (fractal, rotation) in [
+        (Fractal::default(), 0.0),
+        (Fractal::default(), 1.0),
+        (tricorn, 0.0),
+        (trapped, 0.0),
+    ] {
+        view.rotation = rotation;
+        let mut expected = vec![Sample::default(); bounds.0 * bounds.1];
+        crate::render(&mut expected, bounds, &view, &fractal, 4);
+        let mut actual = vec![Sample::default(); bounds.0 * bounds.1];
+        render(&mut actual, bounds, &view, 80, &fractal, 4);
+        let differences = expected
+            .iter()
+            .zip(&actual)
+            .filter(|(e, a)| e.count != a.count)
+            .count();
+        assert!(differences <= bounds.0 * bounds.1 / 100);
+        for (e, a) in expected.iter().zip(&actual) {
+            if e.count == a.count && e.distance.is_finite() {
+                assert!((e.distance - a.distance).abs() <= e.distance * 1e-3);
+            }
+            if e.count == a.count && e.trap.is_finite() {
+                assert!((e.trap - a.trap).abs() <= 1e-4);
+            }
+        }
+    }
+}
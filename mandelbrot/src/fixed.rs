@@ -0,0 +1,375 @@
+use num::bigint::BigInt;
+use num::traits::{Float, One, Signed, ToPrimitive, Zero};
+use num::Complex;
+use std::fmt;
+use std::ops::{Add, Mul, Neg, Sub};
+use std::str::FromStr;
+
+/// An arbitrary-precision fixed-point number: `value / 2^bits`.
+///
+/// Arithmetic between two `Fixed` values requires them to have the same number of
+/// fraction bits; use `with_bits` to convert.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fixed {
+    value: BigInt,
+    bits: u32,
+}
+
+impl Fixed {
+    pub fn zero(bits: u32) -> Fixed {
+        Fixed {
+            value: BigInt::zero(),
+            bits,
+        }
+    }
+
+    /// Convert `x` exactly, as long as `bits` is enough to hold its fraction.
+    pub fn from_f64(x: f64, bits: u32) -> Fixed {
+        let (mantissa, exponent, sign) = x.integer_decode();
+        let mut value = BigInt::from(mantissa);
+        let shift = exponent as i64 + bits as i64;
+        if shift >= 0 {
+            value <<= shift as usize;
+        } else {
+            value >>= (-shift) as usize;
+        }
+        if sign < 0 {
+            value = -value;
+        }
+        Fixed { value, bits }
+    }
+
+    /// The nearest `f64`. Only the 64 most significant bits of the value are converted,
+    /// with the lowest of them set if any of the bits dropped were, which is enough for
+    /// them to round the same way the whole value would.
+    pub fn to_f64(&self) -> f64 {
+        let magnitude = self.value.abs();
+        let excess = magnitude.bits().saturating_sub(64) as usize;
+        let mut top = &magnitude >> excess;
+        if &top << excess != magnitude {
+            top |= BigInt::one();
+        }
+        let top = top.to_f64().unwrap_or(0.0) * 2f64.powi(excess as i32 - self.bits as i32);
+        if self.value.is_negative() {
+            -top
+        } else {
+            top
+        }
+    }
+
+    /// The number of fraction bits this value carries.
+    pub fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    /// The same value with `bits` fraction bits, truncating if `bits` is smaller.
+    pub fn with_bits(&self, bits: u32) -> Fixed {
+        let value = if bits >= self.bits {
+            &self.value << (bits - self.bits) as usize
+        } else {
+            &self.value >> (self.bits - bits) as usize
+        };
+        Fixed { value, bits }
+    }
+
+    pub fn abs(&self) -> Fixed {
+        Fixed {
+            value: self.value.abs(),
+            bits: self.bits,
+        }
+    }
+
+    /// The value in decimal, rounded to `digits` places after the point.
+    pub fn to_decimal(&self, digits: usize) -> String {
+        let scale = BigInt::from(10).pow(digits as u32);
+        let half = (BigInt::one() << self.bits as usize) >> 1;
+        let scaled: BigInt = (self.value.abs() * scale + half) >> self.bits as usize;
+        let mut text = scaled.to_string();
+        if digits > 0 {
+            if text.len() <= digits {
+                text.insert_str(0, &"0".repeat(digits + 1 - text.len()));
+            }
+            text.insert(text.len() - digits, '.');
+        }
+        if self.value.is_negative() && !scaled.is_zero() {
+            text.insert(0, '-');
+        }
+        text
+    }
+
+    /// Multiply by the fraction `numerator / denominator`.
+    pub fn scale(&self, numerator: usize, denominator: usize) -> Fixed {
+        Fixed {
+            value: &self.value * BigInt::from(numerator) / BigInt::from(denominator),
+            bits: self.bits,
+        }
+    }
+}
+
+impl<'a> Add for &'a Fixed {
+    type Output = Fixed;
+    fn add(self, other: &'a Fixed) -> Fixed {
+        assert_eq!(self.bits, other.bits);
+        Fixed {
+            value: &self.value + &other.value,
+            bits: self.bits,
+        }
+    }
+}
+
+impl<'a> Sub for &'a Fixed {
+    type Output = Fixed;
+    fn sub(self, other: &'a Fixed) -> Fixed {
+        assert_eq!(self.bits, other.bits);
+        Fixed {
+            value: &self.value - &other.value,
+            bits: self.bits,
+        }
+    }
+}
+
+impl<'a> Mul for &'a Fixed {
+    type Output = Fixed;
+    fn mul(self, other: &'a Fixed) -> Fixed {
+        assert_eq!(self.bits, other.bits);
+        Fixed {
+            value: (&self.value * &other.value) >> self.bits as usize,
+            bits: self.bits,
+        }
+    }
+}
+
+impl Neg for &Fixed {
+    type Output = Fixed;
+    fn neg(self) -> Fixed {
+        Fixed {
+            value: -&self.value,
+            bits: self.bits,
+        }
+    }
+}
+
+impl FromStr for Fixed {
+    type Err = String;
+
+    /// Parse a decimal number such as `-0.7436438870371587047521915` or `1.5e-20`,
+    /// choosing enough fraction bits to represent every digit given.
+    fn from_str(s: &str) -> Result<Fixed, String> {
+        let error = || format!("invalid number '{}'", s);
+        let (number, exponent) = match s.find(['e', 'E']) {
+            Some(index) => (
+                &s[..index],
+                i64::from_str(&s[index + 1..]).map_err(|_| error())?,
+            ),
+            None => (s, 0),
+        };
+        let (negative, number) = match number.strip_prefix('-') {
+            Some(rest) => (true, rest),
+            None => (false, number.strip_prefix('+').unwrap_or(number)),
+        };
+        let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+        let digits = format!("{}{}", whole, fraction);
+        if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(error());
+        }
+
+        // The number is `digits * 10^decimal_exponent`.
+        let decimal_exponent = exponent - fraction.len() as i64;
+        let mut value = BigInt::from_str(&digits).map_err(|_| error())?;
+        let bits = if decimal_exponent < 0 {
+            // Each decimal digit needs a little over three and a third bits.
+            let bits = 64 + (-decimal_exponent as f64 * 10f64.log2()).ceil() as u32;
+            let divisor = BigInt::from(10).pow((-decimal_exponent) as u32);
+            value = ((value << bits as usize) + &divisor / 2) / divisor;
+            bits
+        } else {
+            value *= BigInt::from(10).pow(decimal_exponent as u32);
+            value <<= 64;
+            64
+        };
+        if negative {
+            value = -value;
+        }
+        Ok(Fixed { value, bits })
+    }
+}
+
+impl fmt::Display for Fixed {
+    /// Write every digit of the value: a fraction with `bits` binary places has no more
+    /// than `bits` decimal places.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self.to_decimal(self.bits as usize);
+        match text.contains('.') {
+            true => f.write_str(text.trim_end_matches('0').trim_end_matches('.')),
+            false => f.write_str(&text),
+        }
+    }
+}
+
+#[test]
+fn test_fixed_decimal() {
+    let x = Fixed::from_str("-1.20").unwrap();
+    assert_eq!(x.to_decimal(2), "-1.20");
+    assert_eq!(x.to_decimal(0), "-1");
+    assert_eq!(Fixed::from_f64(2.5, 8).to_decimal(0), "3");
+    assert_eq!(Fixed::from_f64(0.0625, 8).to_decimal(3), "0.063");
+    assert_eq!(Fixed::from_f64(-0.001, 60).to_decimal(2), "0.00");
+    assert_eq!(Fixed::from_f64(-0.75, 8).to_string(), "-0.75");
+    assert_eq!(Fixed::from_f64(3.0, 8).to_string(), "3");
+    assert_eq!(
+        Fixed::from_f64(0.1, 60).to_string(),
+        "0.1000000000000000055511151231257827021181583404541015625"
+    );
+    // Every digit is written, so the value reads back exactly, if with more bits.
+    let third = Fixed::from_str("0.333333333333333333333333333333").unwrap();
+    for x in [x, third, Fixed::from_f64(-1e-10, 100)] {
+        let y = Fixed::from_str(&x.to_string()).unwrap();
+        assert_eq!(y.with_bits(x.bits()), x);
+        assert_eq!(y.to_f64(), x.to_f64());
+    }
+}
+
+#[test]
+fn test_fixed_conversions() {
+    for x in [0.0, 1.0, -2.5, 0.1, -1.0e-10, 12345.678] {
+        assert_eq!(Fixed::from_f64(x, 100).to_f64(), x);
+    }
+    assert_eq!(Fixed::from_f64(0.75, 8).with_bits(1).to_f64(), 0.5);
+    assert_eq!(Fixed::from_f64(0.75, 8).with_bits(40).to_f64(), 0.75);
+    assert_eq!(Fixed::from_f64(-3.0, 20).abs().to_f64(), 3.0);
+    assert_eq!(Fixed::from_f64(3.0, 20).scale(5, 4).to_f64(), 3.75);
+}
+
+#[test]
+fn test_to_f64_rounding() {
+    // Just over halfway between 1 and the next f64 up, by less than the 64 bits kept:
+    // truncating to those bits first would leave a tie, rounding down to 1.
+    let value = ((BigInt::from(1u64 << 63) + (1 << 10)) << 1) + 1;
+    let x = Fixed { value, bits: 64 };
+    assert_eq!(x.to_f64(), 1.0 + f64::EPSILON);
+    assert_eq!((-&x).to_f64(), -1.0 - f64::EPSILON);
+    // Exactly halfway rounds to even, whichever way that is.
+    let tie = Fixed {
+        value: (BigInt::from(1u64 << 63) + (1 << 10)) << 1,
+        bits: 64,
+    };
+    assert_eq!(tie.to_f64(), 1.0);
+    // The value read back from every digit of a decimal rounds as the decimal does.
+    let exact = Fixed::from_f64(0.1, 70);
+    assert_eq!(exact.to_string().parse(), Ok(exact.to_f64()));
+}
+
+#[test]
+fn test_fixed_arithmetic() {
+    let a = Fixed::from_f64(1.5, 60);
+    let b = Fixed::from_f64(-0.25, 60);
+    assert_eq!((&a + &b).to_f64(), 1.25);
+    assert_eq!((&a - &b).to_f64(), 1.75);
+    assert_eq!((&a * &b).to_f64(), -0.375);
+    assert_eq!((-&a).to_f64(), -1.5);
+    assert_eq!((&b * &b).to_f64(), 0.0625);
+}
+
+#[test]
+fn test_parse_fixed() {
+    assert_eq!(Fixed::from_str("1.25").unwrap().to_f64(), 1.25);
+    assert_eq!(Fixed::from_str("-0.0625").unwrap().to_f64(), -0.0625);
+    assert_eq!(Fixed::from_str("+3").unwrap().to_f64(), 3.0);
+    assert_eq!(Fixed::from_str("15e-1").unwrap().to_f64(), 1.5);
+    assert_eq!(Fixed::from_str("2.5E2").unwrap().to_f64(), 250.0);
+    assert!(Fixed::from_str("").is_err());
+    assert!(Fixed::from_str("1.2.3").is_err());
+    assert!(Fixed::from_str("1e").is_err());
+    assert!(Fixed::from_str("x").is_err());
+
+    // Digits far beyond the reach of an f64 must survive.
+    let a = Fixed::from_str("-0.74364388703715870475219150611477").unwrap();
+    let b = Fixed::from_str("-0.74364388703715870475219150611476").unwrap();
+    assert!(a.bits() > 100);
+    let difference = (&b - &a).to_f64();
+    assert!((difference - 1e-32).abs() < 1e-40);
+}
+
+/// A complex number with `Fixed` parts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FixedComplex {
+    pub re: Fixed,
+    pub im: Fixed,
+}
+
+impl FixedComplex {
+    pub fn from_complex(z: Complex<f64>, bits: u32) -> FixedComplex {
+        FixedComplex {
+            re: Fixed::from_f64(z.re, bits),
+            im: Fixed::from_f64(z.im, bits),
+        }
+    }
+
+    pub fn to_complex(&self) -> Complex<f64> {
+        Complex {
+            re: self.re.to_f64(),
+            im: self.im.to_f64(),
+        }
+    }
+
+    pub fn with_bits(&self, bits: u32) -> FixedComplex {
+        FixedComplex {
+            re: self.re.with_bits(bits),
+            im: self.im.with_bits(bits),
+        }
+    }
+
+    pub fn conj(&self) -> FixedComplex {
+        FixedComplex {
+            re: self.re.clone(),
+            im: -&self.im,
+        }
+    }
+
+    pub fn square(&self) -> FixedComplex {
+        let re = &(&self.re * &self.re) - &(&self.im * &self.im);
+        let im = &self.re * &self.im;
+        FixedComplex { re, im: &im + &im }
+    }
+
+    pub fn norm_sqr(&self) -> f64 {
+        self.to_complex().norm_sqr()
+    }
+}
+
+impl<'a> Add for &'a FixedComplex {
+    type Output = FixedComplex;
+    fn add(self, other: &'a FixedComplex) -> FixedComplex {
+        FixedComplex {
+            re: &self.re + &other.re,
+            im: &self.im + &other.im,
+        }
+    }
+}
+
+impl<'a> Mul for &'a FixedComplex {
+    type Output = FixedComplex;
+    fn mul(self, other: &'a FixedComplex) -> FixedComplex {
+        FixedComplex {
+            re: &(&self.re * &other.re) - &(&self.im * &other.im),
+            im: &(&self.re * &other.im) + &(&self.im * &other.re),
+        }
+    }
+}
+
+#[test]
+fn test_fixed_complex() {
+    let z = FixedComplex::from_complex(Complex { re: 1.0, im: 2.0 }, 50);
+    let w = FixedComplex::from_complex(Complex { re: -0.5, im: 0.25 }, 50);
+    assert_eq!(z.square().to_complex(), Complex { re: -3.0, im: 4.0 });
+    assert_eq!(
+        (&z * &w).to_complex(),
+        Complex {
+            re: -1.0,
+            im: -0.75
+        }
+    );
+    assert_eq!((&z + &w).to_complex(), Complex { re: 0.5, im: 2.25 });
+    assert_eq!(z.conj().to_complex(), Complex { re: 1.0, im: -2.0 });
+    assert_eq!(z.norm_sqr(), 5.0);
+}
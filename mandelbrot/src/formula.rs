@@ -0,0 +1,255 @@
+use crate::fixed::FixedComplex;
+use num::Complex;
+use std::fmt;
+use std::str::FromStr;
+
+/// The iteration `z = f(z, c)` whose escape time we draw.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Formula {
+    /// `z = z² + c`
+    Mandelbrot,
+    /// `z = (|Re z| + i|Im z|)² + c`
+    BurningShip,
+    /// `z = conj(z)² + c`, also known as the Mandelbar set.
+    Tricorn,
+    /// `z = z^d + c` for a real exponent `d` greater than one.
+    Multibrot(f64),
+}
+
+impl Formula {
+    /// Apply one step of the iteration to `z`.
+    pub fn step(&self, z: Complex<f64>, c: Complex<f64>) -> Complex<f64> {
+        match *self {
+            Formula::Mandelbrot => z * z + c,
+            Formula::BurningShip => {
+                let z = Complex {
+                    re: z.re.abs(),
+                    im: z.im.abs(),
+                };
+                z * z + c
+            }
+            Formula::Tricorn => {
+                let z = z.conj();
+                z * z + c
+            }
+            Formula::Multibrot(d) if d.fract() == 0.0 && d <= i32::MAX as f64 => {
+                z.powi(d as i32) + c
+            }
+            Formula::Multibrot(d) => z.powf(d) + c,
+        }
+    }
+
+    /// Carry a small change `dz` in `z` through one step of the iteration, not counting
+    /// any change in `c`: the derivative of `step` in the direction of `dz`. For formulas
+    /// that aren't `is_holomorphic`, the result depends on which way `dz` points, not just
+    /// on its size.
+    pub fn step_derivative(&self, z: Complex<f64>, dz: Complex<f64>) -> Complex<f64> {
+        match *self {
+            Formula::Mandelbrot => z * dz * 2.0,
+            Formula::BurningShip => {
+                // Folding `z` into the first quadrant flips `dz` along with it.
+                let fold = |x: f64, dx: f64| if x < 0.0 { (-x, -dx) } else { (x, dx) };
+                let (re, dre) = fold(z.re, dz.re);
+                let (im, dim) = fold(z.im, dz.im);
+                Complex { re, im } * Complex { re: dre, im: dim } * 2.0
+            }
+            Formula::Tricorn => (z * dz).conj() * 2.0,
+            Formula::Multibrot(d) if d.fract() == 0.0 && d <= i32::MAX as f64 => {
+                z.powi(d as i32 - 1) * dz * d
+            }
+            Formula::Multibrot(d) => z.powf(d - 1.0) * dz * d,
+        }
+    }
+
+    /// Whether `step` is complex-differentiable, so that it stretches a small change in `z`
+    /// equally in every direction.
+    pub fn is_holomorphic(&self) -> bool {
+        matches!(self, Formula::Mandelbrot | Formula::Multibrot(_))
+    }
+
+    /// Whether `step_fixed` can apply this formula exactly: everything except a
+    /// Multibrot with a fractional exponent.
+    pub fn supports_fixed(&self) -> bool {
+        match *self {
+            Formula::Multibrot(d) => d.fract() == 0.0,
+            _ => true,
+        }
+    }
+
+    /// Apply one step of the iteration to `z` in arbitrary precision.
+    pub fn step_fixed(&self, z: &FixedComplex, c: &FixedComplex) -> FixedComplex {
+        match *self {
+            Formula::Mandelbrot => &z.square() + c,
+            Formula::BurningShip => {
+                let z = FixedComplex {
+                    re: z.re.abs(),
+                    im: z.im.abs(),
+                };
+                &z.square() + c
+            }
+            Formula::Tricorn => &z.conj().square() + c,
+            Formula::Multibrot(d) => {
+                assert!(self.supports_fixed());
+                let mut power = d as u64;
+                let mut base = z.clone();
+                let mut result: Option<FixedComplex> = None;
+                while power > 0 {
+                    if power & 1 == 1 {
+                        result = Some(match result {
+                            None => base.clone(),
+                            Some(r) => &r * &base,
+                        });
+                    }
+                    power >>= 1;
+                    if power > 0 {
+                        base = base.square();
+                    }
+                }
+                &result.unwrap() + c
+            }
+        }
+    }
+
+    /// How fast `|z|` grows once it is large: roughly `|z|` raised to this power per step.
+    pub fn degree(&self) -> f64 {
+        match *self {
+            Formula::Multibrot(d) => d,
+            _ => 2.0,
+        }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = String;
+
+    /// Parse `mandelbrot`, `burning-ship`, `tricorn`, or `multibrot:D` for an exponent `D`.
+    fn from_str(s: &str) -> Result<Formula, String> {
+        match s {
+            "mandelbrot" => return Ok(Formula::Mandelbrot),
+            "burning-ship" => return Ok(Formula::BurningShip),
+            "tricorn" => return Ok(Formula::Tricorn),
+            _ => {}
+        }
+        match s.strip_prefix("multibrot:").map(f64::from_str) {
+            Some(Ok(d)) if d > 1.0 && d.is_finite() => Ok(Formula::Multibrot(d)),
+            Some(_) => Err(format!("multibrot exponent in '{}' must exceed 1", s)),
+            None => Err(format!("unknown formula '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for Formula {
+    /// Write the formula's name as `FromStr` parses it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Formula::Mandelbrot => write!(f, "mandelbrot"),
+            Formula::BurningShip => write!(f, "burning-ship"),
+            Formula::Tricorn => write!(f, "tricorn"),
+            Formula::Multibrot(d) => write!(f, "multibrot:{}", d),
+        }
+    }
+}
+
+#[cfg(test)]
+fn c(re: f64, im: f64) -> Complex<f64> {
+    Complex { re, im }
+}
+
+#[test]
+fn test_mandelbrot_step() {
+    assert_eq!(
+        Formula::Mandelbrot.step(c(1.0, 1.0), c(0.5, 0.0)),
+        c(0.5, 2.0)
+    );
+    assert_eq!(Formula::Mandelbrot.degree(), 2.0);
+}
+
+#[test]
+fn test_burning_ship_step() {
+    // Folding into the first quadrant makes every sign of `z` behave the same.
+    for z in [c(1.0, 2.0), c(-1.0, 2.0), c(1.0, -2.0), c(-1.0, -2.0)] {
+        assert_eq!(Formula::BurningShip.step(z, c(0.0, 1.0)), c(-3.0, 5.0));
+    }
+}
+
+#[test]
+fn test_tricorn_step() {
+    assert_eq!(
+        Formula::Tricorn.step(c(1.0, 2.0), c(0.0, 0.0)),
+        c(-3.0, -4.0)
+    );
+    assert_eq!(
+        Formula::Tricorn.step(c(1.0, -2.0), c(1.0, 1.0)),
+        c(-2.0, 5.0)
+    );
+}
+
+#[test]
+fn test_multibrot_step() {
+    let z = c(0.3, -1.1);
+    let k = c(-0.2, 0.4);
+    assert_eq!(
+        Formula::Multibrot(2.0).step(z, k),
+        Formula::Mandelbrot.step(z, k)
+    );
+    assert_eq!(Formula::Multibrot(3.0).step(c(0.0, 1.0), k), c(-0.2, -0.6));
+    let w = Formula::Multibrot(2.5).step(c(4.0, 0.0), c(1.0, 0.0));
+    assert!((w - c(33.0, 0.0)).norm() < 1e-9);
+    assert_eq!(Formula::Multibrot(2.5).degree(), 2.5);
+}
+
+#[test]
+fn test_step_derivative() {
+    // Compare with the change a small nudge in `z` actually makes, in several directions.
+    let (z, k, h) = (c(0.375, -1.125), c(-0.5, 0.25), 1e-7);
+    for formula in [
+        Formula::Mandelbrot,
+        Formula::BurningShip,
+        Formula::Tricorn,
+        Formula::Multibrot(3.0),
+        Formula::Multibrot(2.5),
+    ] {
+        for dz in [c(1.0, 0.0), c(0.0, 1.0), c(-0.6, 0.8)] {
+            let nudged = (formula.step(z + dz * h, k) - formula.step(z, k)) / h;
+            let derivative = formula.step_derivative(z, dz);
+            assert!((nudged - derivative).norm() < 1e-5, "{:?}", formula);
+        }
+    }
+}
+
+#[test]
+fn test_fixed_steps_match() {
+    let z = c(0.375, -1.125);
+    let k = c(-0.5, 0.25);
+    let (fz, fk) = (
+        FixedComplex::from_complex(z, 80),
+        FixedComplex::from_complex(k, 80),
+    );
+    for formula in [
+        Formula::Mandelbrot,
+        Formula::BurningShip,
+        Formula::Tricorn,
+        Formula::Multibrot(3.0),
+        Formula::Multibrot(6.0),
+    ] {
+        assert!(formula.supports_fixed());
+        let exact = formula.step_fixed(&fz, &fk).to_complex();
+        assert!((exact - formula.step(z, k)).norm() < 1e-12, "{:?}", formula);
+    }
+    assert!(!Formula::Multibrot(2.5).supports_fixed());
+}
+
+#[test]
+fn test_parse_formula() {
+    assert_eq!("mandelbrot".parse(), Ok(Formula::Mandelbrot));
+    assert_eq!("burning-ship".parse(), Ok(Formula::BurningShip));
+    assert_eq!("tricorn".parse(), Ok(Formula::Tricorn));
+    assert_eq!("multibrot:3".parse(), Ok(Formula::Multibrot(3.0)));
+    assert_eq!("multibrot:2.5".parse(), Ok(Formula::Multibrot(2.5)));
+    assert!("multibrot:1".parse::<Formula>().is_err());
+    assert!("multibrot:x".parse::<Formula>().is_err());
+    assert!("newton".parse::<Formula>().is_err());
+    for name in ["mandelbrot", "burning-ship", "tricorn", "multibrot:2.5"] {
+        assert_eq!(name.parse::<Formula>().unwrap().to_string(), name);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::Sample;
+use std::collections::BTreeMap;
+
+/// How many of an image's samples escaped at each iteration count, for histogram coloring:
+/// spreading the escaped samples evenly along the palette, so that every stretch of it
+/// colors about as much of the image as any other, however the counts bunch up.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Histogram {
+    /// The number of samples that escaped at each count.
+    tally: BTreeMap<u32, u64>,
+    /// Each count that occurs, in increasing order, with the number of samples that
+    /// escaped at lower counts.
+    below: Vec<(u32, u64)>,
+    /// The number of samples that escaped.
+    total: u64,
+}
+
+impl Histogram {
+    /// The histogram of `samples`.
+    pub fn of(samples: &[Sample]) -> Histogram {
+        let mut histogram = Histogram::default();
+        histogram.add(samples);
+        histogram
+    }
+
+    /// Count `samples` in too, for images too large to hold at once.
+    pub fn add(&mut self, samples: &[Sample]) {
+        for sample in samples.iter().filter(|sample| sample.escaped()) {
+            *self.tally.entry(sample.count).or_insert(0) += 1;
+        }
+        self.below.clear();
+        self.total = 0;
+        for (&count, &samples) in &self.tally {
+            self.below.push((count, self.total));
+            self.total += samples;
+        }
+    }
+
+    /// The fraction of escaped samples whose counts are less than `count`.
+    fn fraction_below(&self, count: u64) -> f64 {
+        let i = self.below.partition_point(|&(c, _)| (c as u64) < count);
+        let below = self
+            .below
+            .get(i)
+            .map_or(self.total, |&(_, samples)| samples);
+        below as f64 / self.total as f64
+    }
+
+    /// The position in `0.0..=1.0` along the palette for an escape count: the fraction of
+    /// escaped samples that escaped no later. A continuous count lands between the
+    /// positions of the whole counts on either side.
+    pub fn position(&self, count: f64) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        let whole = count.max(0.0).floor();
+        let low = self.fraction_below(whole as u64 + 1);
+        let high = self.fraction_below(whole as u64 + 2);
+        low + (count - whole).clamp(0.0, 1.0) * (high - low)
+    }
+}
+
+#[test]
+fn test_histogram() {
+    let escaped = |count| Sample {
+        count,
+        fraction: 0.0,
+        distance: f32::INFINITY,
+        trap: f32::INFINITY,
+    };
+    // Most samples escape at 10, a few at 3 and 200: a wide gap in counts, but in the
+    // histogram the three counts sit evenly along the palette by share of samples.
+    let mut samples = vec![escaped(10); 6];
+    samples.extend([escaped(3), escaped(200), Sample::INTERIOR]);
+    let histogram = Histogram::of(&samples);
+    assert_eq!(histogram.position(3.0), 1.0 / 8.0);
+    assert_eq!(histogram.position(10.0), 7.0 / 8.0);
+    assert_eq!(histogram.position(200.0), 1.0);
+    // Counts no sample has share the position of the count below them, and continuous
+    // counts interpolate between whole ones.
+    assert_eq!(histogram.position(50.0), 7.0 / 8.0);
+    assert_eq!(histogram.position(9.5), (1.0 / 8.0 + 7.0 / 8.0) / 2.0);
+    assert_eq!(histogram.position(0.0), 0.0);
+
+    // Adding samples a band at a time gives the same histogram as adding them all at once.
+    let mut banded = Histogram::default();
+    for band in samples.chunks(4) {
+        banded.add(band);
+    }
+    assert_eq!(banded, histogram);
+    assert_eq!(Histogram::of(&[Sample::INTERIOR]).position(5.0), 0.0);
+}
@@ -1,22 +1,902 @@
+mod animated;
+mod animation;
+mod buddhabrot;
+mod deep;
+mod fixed;
+mod formula;
+mod histogram;
+mod newton;
+mod output;
+mod palette;
+mod perturbation;
+mod pngstream;
+mod pyramid;
+mod scene;
+mod serve;
+mod simd;
+mod supersample;
+mod tiles;
+mod trap;
+mod view;
+
+use animated::Playback;
+use animation::{Animation, Keyframe};
+use buddhabrot::Buddhabrot;
+use fixed::{Fixed, FixedComplex};
+use formula::Formula;
+use histogram::Histogram;
 use image::png::PNGEncoder;
 use image::ColorType;
+use newton::Polynomial;
 use num::Complex;
+use output::{ImageFormat, ImageWriter, RawReader, SampleGrid};
+use palette::Palette;
+use scene::Scene;
+use std::collections::VecDeque;
 use std::env;
 use std::fs::File;
+use std::io::{BufReader, BufWriter};
 use std::str::FromStr;
+use tiles::{render_tiles, tiles, Tile, TILE_SIZE};
+use trap::Trap;
+use view::View;
+
+/// The state of a point's orbit when it escaped: the iteration count, and the value of
+/// `z` a few iterations later (see `EXTRA_ITERATIONS`).
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Escape {
+    count: usize,
+    z: Complex<f64>,
+    /// The derivative of that `z` with respect to the pixel, if it was tracked.
+    derivative: Option<Complex<f64>>,
+    /// The closest the orbit came to the trap before escaping, if there was one.
+    trap: Option<f64>,
+}
+
+impl Escape {
+    /// A continuous iteration count, interpolating between `count` and `count + 1`
+    /// according to how far `z` overshot the escape radius `bailout`. `degree` is that of
+    /// the formula that produced the orbit.
+    fn smooth_count(&self, degree: f64, bailout: f64) -> f64 {
+        let log_ratio = self.z.norm().ln() / bailout.ln();
+        let smooth = (self.count + EXTRA_ITERATIONS) as f64 + 1.0 - log_ratio.ln() / degree.ln();
+        smooth.max(0.0)
+    }
+
+    /// An estimate of the distance from the pixel to the set, from how fast `z` was moving
+    /// as the pixel moved: `|z| ln |z| / |dz|`. Once `|z|` is large, the true distance lies
+    /// between half and twice this.
+    fn distance(&self) -> Option<f64> {
+        let norm = self.z.norm();
+        self.derivative
+            .map(|derivative| norm * norm.ln() / derivative.norm())
+    }
+}
+
+/// The derivative of an orbit with respect to the pixel, followed alongside the orbit for
+/// `Escape::distance`.
+#[derive(Clone, Copy, Debug)]
+struct Derivative {
+    /// The derivative of `z` as the pixel moves along the real axis, and for formulas that
+    /// aren't complex-differentiable, as it moves along the imaginary axis too: such
+    /// formulas stretch some directions more than others.
+    dz: [Complex<f64>; 2],
+    /// The derivative of `c` in the same directions.
+    dc: [Complex<f64>; 2],
+    directions: usize,
+}
+
+impl Derivative {
+    /// The derivative at the start of the orbit of a pixel of `formula` drawn in `mode`.
+    fn new(formula: &Formula, mode: Mode) -> Derivative {
+        let one = Complex { re: 1.0, im: 0.0 };
+        let i = Complex { re: 0.0, im: 1.0 };
+        let (dz, dc) = match mode {
+            Mode::Mandelbrot => ([ZERO; 2], [one, i]),
+            Mode::Julia(_) => ([one, i], [ZERO; 2]),
+        };
+        Derivative {
+            dz,
+            dc,
+            directions: if formula.is_holomorphic() { 1 } else { 2 },
+        }
+    }
+
+    /// Follow `formula` taking one step from `z`.
+    fn step(&mut self, formula: &Formula, z: Complex<f64>) {
+        for k in 0..self.directions {
+            self.dz[k] = formula.step_derivative(z, self.dz[k]) + self.dc[k];
+        }
+    }
+
+    /// The derivative in whichever direction changes `z` fastest, which comes nearest to
+    /// leaving the set.
+    fn steepest(&self) -> Complex<f64> {
+        let dz = &self.dz[..self.directions];
+        *dz.iter()
+            .max_by(|a, b| a.norm_sqr().total_cmp(&b.norm_sqr()))
+            .unwrap()
+    }
+}
+
+/// What to follow along an orbit besides `z` itself, for the shadings that need more than
+/// when it escaped.
+#[derive(Clone, Copy, Debug)]
+struct Tracking {
+    /// The derivative of `z` with respect to the pixel, for `Escape::distance`.
+    derivative: Option<Derivative>,
+    /// The trap to measure the orbit against, for `Escape::trap`.
+    trap: Option<Trap>,
+    /// The orbit's closest approach to `trap` so far.
+    nearest: f64,
+}
+
+impl Tracking {
+    /// Follow nothing but `z`.
+    const NONE: Tracking = Tracking {
+        derivative: None,
+        trap: None,
+        nearest: f64::INFINITY,
+    };
+
+    /// Follow `formula` taking one step from `z`.
+    fn step(&mut self, formula: &Formula, z: Complex<f64>) {
+        if let Some(derivative) = &mut self.derivative {
+            derivative.step(formula, z);
+        }
+    }
+
+    /// Note that the orbit has reached `z`.
+    fn visit(&mut self, z: Complex<f64>) {
+        if let Some(trap) = &self.trap {
+            self.nearest = self.nearest.min(trap.distance(z));
+        }
+    }
+
+    /// The `Escape` for an orbit that escaped at iteration `count` and reached `z` a few
+    /// iterations later.
+    fn escape(&self, count: usize, z: Complex<f64>) -> Escape {
+        Escape {
+            count,
+            z,
+            derivative: self.derivative.map(|derivative| derivative.steepest()),
+            trap: self.trap.map(|_| self.nearest),
+        }
+    }
+}
+
+#[test]
+fn test_derivative() {
+    // Moving `c` along either axis moves the Tricorn's `conj(z)² + c` differently.
+    let c = Complex { re: 0.3, im: 0.4 };
+    let mut derivative = Derivative::new(&Formula::Tricorn, Mode::Mandelbrot);
+    let mut z = ZERO;
+    for _ in 0..3 {
+        derivative.step(&Formula::Tricorn, z);
+        z = Formula::Tricorn.step(z, c);
+    }
+    let nudged = |dc: Complex<f64>| {
+        let mut w = ZERO;
+        for _ in 0..3 {
+            w = Formula::Tricorn.step(w, c + dc * 1e-7);
+        }
+        (w - z) / 1e-7
+    };
+    for (k, dc) in [
+        (0, Complex { re: 1.0, im: 0.0 }),
+        (1, Complex { re: 0.0, im: 1.0 }),
+    ] {
+        assert!((derivative.dz[k] - nudged(dc)).norm() < 1e-5);
+    }
+    assert_eq!(
+        derivative.steepest().norm(),
+        derivative.dz[0].norm().max(derivative.dz[1].norm())
+    );
+}
+
+/// The escape radius to use when none is specified: the smallest that is certain to hold
+/// for the Mandelbrot set.
+const ESCAPE_RADIUS: f64 = 2.0;
+
+/// The iteration limit to use when none is specified.
+const DEFAULT_LIMIT: usize = 255;
 
-/// Try to determine if `c` is in the Mandelbrot set, using at most `limit` iterations to decide.
-fn escape_time(c: Complex<f64>, limit: usize) -> Option<usize> {
-    let mut z = Complex { re: 0.0, im: 0.0 };
+/// How many more times to iterate an orbit after it escapes. With an escape radius as
+/// small as two, `c` still pulls noticeably on `z` at the moment of escape; a few more
+/// squarings make `|z|` large enough for `Escape::smooth_count` to be accurate.
+const EXTRA_ITERATIONS: usize = 3;
+
+/// If an orbit comes back within this squared distance of a value it had earlier, we
+/// take it to have settled into a cycle, and so never to escape.
+const PERIODICITY_TOLERANCE: f64 = 1e-28;
+
+/// Iterate `formula` from the given starting `z`, using at most `limit` iterations to
+/// decide whether the orbit stays bounded.
+///
+/// If it escapes, return the iteration at which the orbit left the circle of radius
+/// `bailout` centered on the origin, along with the orbit's value shortly afterwards.
+/// Follow whatever else `tracking` asks for along the way: the derivative of `z` with
+/// respect to the pixel, for `Escape::distance`, and the orbit's closest approach to a
+/// trap, which counts every value after the starting one up to the first outside the
+/// circle.
+///
+/// Orbits inside the set usually settle into a cycle long before `limit`. To notice that,
+/// we use Brent's method: remember the orbit's value at every power-of-two iteration, and
+/// stop as soon as the orbit returns to the value last remembered.
+fn escape_time(
+    formula: &Formula,
+    mut z: Complex<f64>,
+    c: Complex<f64>,
+    mut tracking: Tracking,
+    limit: usize,
+    bailout: f64,
+) -> Option<Escape> {
+    let mut saved = z;
+    let mut next_save = 1;
+    let step = |z: Complex<f64>, tracking: &mut Tracking| {
+        tracking.step(formula, z);
+        formula.step(z, c)
+    };
+    for i in 0..limit {
+        if z.norm_sqr() > bailout * bailout {
+            for _ in 0..EXTRA_ITERATIONS {
+                z = step(z, &mut tracking);
+            }
+            return Some(tracking.escape(i, z));
+        }
+        z = step(z, &mut tracking);
+        tracking.visit(z);
+        if (z - saved).norm_sqr() < PERIODICITY_TOLERANCE {
+            return None;
+        }
+        if i + 1 == next_save {
+            saved = z;
+            next_save *= 2;
+        }
+    }
+    None
+}
+
+/// Return true if `c` lies in the Mandelbrot set's main cardioid or its period-2 bulb,
+/// which together make up most of its area. Iterating such points always runs to the
+/// limit, so testing for them first saves a lot of time.
+fn in_cardioid_or_bulb(c: Complex<f64>) -> bool {
+    let x = c.re - 0.25;
+    let q = x * x + c.im * c.im;
+    let in_cardioid = q * (q + x) <= 0.25 * c.im * c.im;
+    let in_bulb = (c.re + 1.0) * (c.re + 1.0) + c.im * c.im <= 0.0625;
+    in_cardioid || in_bulb
+}
+
+#[test]
+fn test_in_cardioid_or_bulb() {
+    for (re, im) in [
+        (0.0, 0.0),
+        (-0.5, 0.5),
+        (0.24, 0.0),
+        (-1.0, 0.0),
+        (-1.2, 0.1),
+    ] {
+        assert!(in_cardioid_or_bulb(Complex { re, im }));
+    }
+    // In the set, but in neither region.
+    for (re, im) in [(-1.76, 0.0), (-0.12, 0.75), (-0.1, 0.9)] {
+        assert!(!in_cardioid_or_bulb(Complex { re, im }));
+    }
+    // Not in the set at all.
+    for (re, im) in [(0.26, 0.0), (-1.3, 0.0), (-0.5, 0.7)] {
+        assert!(!in_cardioid_or_bulb(Complex { re, im }));
+    }
+}
+
+/// A plain escape-time loop with none of `escape_time`'s shortcuts, for checking them.
+#[cfg(test)]
+fn escape_time_naive(point: Complex<f64>, limit: usize) -> Option<usize> {
+    let mut z = ZERO;
     for i in 0..limit {
         if z.norm_sqr() > 4.0 {
             return Some(i);
         }
-        z = z * z + c;
+        z = z * z + point;
     }
     None
 }
 
+#[test]
+fn test_interior_shortcuts_match_naive() {
+    // A view containing the whole set, including the cardioid, the bulbs, and the
+    // boundary regions where early exits are riskiest.
+    let fractal = Fractal {
+        limit: 1000,
+        ..Fractal::default()
+    };
+    let bounds = (300, 240);
+    let (upper_left, lower_right) = (Complex { re: -2.1, im: 1.2 }, Complex { re: 0.6, im: -1.2 });
+    let mut escapes = vec![None; bounds.0];
+    for row in 0..bounds.1 {
+        let points: Vec<Complex<f64>> = (0..bounds.0)
+            .map(|column| pixel_to_point(bounds, (column, row), upper_left, lower_right))
+            .collect();
+        fractal.escape_times(&points, &mut escapes);
+        for (&point, escape) in points.iter().zip(&escapes) {
+            let expected = escape_time_naive(point, 1000);
+            assert_eq!(escape.map(|e| e.count), expected, "{}", point);
+            assert_eq!(fractal.escape_time(point).map(|e| e.count), expected);
+        }
+    }
+}
+
+/// Time the interior shortcuts against a plain loop on a view dominated by the main
+/// cardioid. Run with `cargo test --release -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_interior_shortcuts() {
+    use std::time::Instant;
+
+    let fractal = Fractal {
+        limit: 1000,
+        ..Fractal::default()
+    };
+    let bounds = (800, 600);
+    let (upper_left, lower_right) = (Complex { re: -1.0, im: 0.6 }, Complex { re: 0.4, im: -0.6 });
+    let points: Vec<Complex<f64>> = (0..bounds.1)
+        .flat_map(|row| (0..bounds.0).map(move |column| (column, row)))
+        .map(|pixel| pixel_to_point(bounds, pixel, upper_left, lower_right))
+        .collect();
+
+    let start = Instant::now();
+    let naive: usize = points
+        .iter()
+        .filter_map(|&point| escape_time_naive(point, 1000))
+        .sum();
+    let naive_time = start.elapsed();
+
+    let start = Instant::now();
+    let shortcut: usize = points
+        .iter()
+        .filter_map(|&point| fractal.escape_time(point))
+        .map(|escape| escape.count)
+        .sum();
+    let shortcut_time = start.elapsed();
+
+    println!(
+        "naive: {:?}, with interior shortcuts: {:?} ({:.1}x faster)",
+        naive_time,
+        shortcut_time,
+        naive_time.as_secs_f64() / shortcut_time.as_secs_f64()
+    );
+    assert_eq!(naive, shortcut);
+    assert!(shortcut_time < naive_time);
+}
+
+#[test]
+fn test_smooth_count() {
+    let limit = 255;
+    assert_eq!(
+        escape_time(
+            &Formula::Mandelbrot,
+            ZERO,
+            Complex { re: -0.5, im: 0.0 },
+            Tracking::NONE,
+            limit,
+            ESCAPE_RADIUS
+        ),
+        None
+    );
+
+    // Along the real axis the integer count drops in steps, but the smooth count should
+    // fall steadily, with no jumps between neighboring samples.
+    let samples: Vec<(usize, f64)> = (0..1000)
+        .map(|i| Complex {
+            re: 0.4 + i as f64 * 0.0005,
+            im: 0.0,
+        })
+        .map(|c| {
+            escape_time(
+                &Formula::Mandelbrot,
+                ZERO,
+                c,
+                Tracking::NONE,
+                limit,
+                ESCAPE_RADIUS,
+            )
+            .unwrap()
+        })
+        .map(|escape| (escape.count, escape.smooth_count(2.0, ESCAPE_RADIUS)))
+        .collect();
+    assert!(samples[0].0 > samples[999].0 + 2);
+    for pair in samples.windows(2) {
+        assert!(pair[1].1 <= pair[0].1);
+        assert!(pair[0].1 - pair[1].1 < 0.05);
+    }
+    for c in [Complex { re: 0.3, im: 0.0 }, Complex { re: -1.0, im: 0.5 }] {
+        let escape = escape_time(
+            &Formula::Mandelbrot,
+            ZERO,
+            c,
+            Tracking::NONE,
+            limit,
+            ESCAPE_RADIUS,
+        )
+        .unwrap();
+        let smooth = escape.smooth_count(2.0, ESCAPE_RADIUS);
+        assert!(smooth >= escape.count as f64 - 1.0 && smooth <= escape.count as f64 + 1.0);
+
+        // A larger bailout takes more iterations to escape, but the smooth count only
+        // shifts by a constant, `log2(ln 100 / ln 2)`.
+        let wide =
+            escape_time(&Formula::Mandelbrot, ZERO, c, Tracking::NONE, limit, 100.0).unwrap();
+        assert!(wide.count > escape.count);
+        let shift = wide.smooth_count(2.0, 100.0) - smooth;
+        assert!((shift - (100f64.ln() / 2f64.ln()).log2()).abs() < 0.01);
+    }
+}
+
+const ZERO: Complex<f64> = Complex { re: 0.0, im: 0.0 };
+
+/// Which of the iteration's two parameters each pixel supplies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    /// Draw the Mandelbrot set: the pixel is `c`, and `z` starts at zero.
+    Mandelbrot,
+    /// Draw the Julia set for the given `c`: the pixel is the starting `z`.
+    Julia(Complex<f64>),
+}
+
+impl Mode {
+    /// Return the starting `z` and the constant `c` for the pixel at `point`.
+    fn orbit(&self, point: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        match *self {
+            Mode::Mandelbrot => (ZERO, point),
+            Mode::Julia(c) => (point, c),
+        }
+    }
+}
+
+/// The fractal to draw: the formula to iterate, how pixels feed into it, and how long
+/// to follow each orbit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Fractal {
+    formula: Formula,
+    mode: Mode,
+    /// The most iterations to spend on any one orbit.
+    limit: usize,
+    /// The escape radius: an orbit that gets further than this from the origin escapes.
+    bailout: f64,
+    /// Whether to estimate each pixel's distance from the set, which costs a little more
+    /// per iteration.
+    distance: bool,
+    /// The trap to measure each orbit's closest approach to, if any.
+    trap: Option<Trap>,
+}
+
+impl Default for Fractal {
+    /// The tool's original fractal: the Mandelbrot set, with 255 iterations.
+    fn default() -> Fractal {
+        Fractal {
+            formula: Formula::Mandelbrot,
+            mode: Mode::Mandelbrot,
+            limit: DEFAULT_LIMIT,
+            bailout: ESCAPE_RADIUS,
+            distance: false,
+            trap: None,
+        }
+    }
+}
+
+impl Fractal {
+    /// Iterate the orbit for the pixel at `point`.
+    fn escape_time(&self, point: Complex<f64>) -> Option<Escape> {
+        if self.known_interior(point) {
+            return None;
+        }
+        let (z, c) = self.mode.orbit(point);
+        let tracking = self.tracking();
+        escape_time(&self.formula, z, c, tracking, self.limit, self.bailout)
+    }
+
+    /// What to follow along each orbit: the derivative if distances are wanted, and the
+    /// trap if there is one.
+    fn tracking(&self) -> Tracking {
+        Tracking {
+            derivative: self
+                .distance
+                .then(|| Derivative::new(&self.formula, self.mode)),
+            trap: self.trap,
+            ..Tracking::NONE
+        }
+    }
+
+    /// Reduce the result of iterating a pixel to what coloring needs, measuring distances
+    /// in units of `spacing`, the distance between neighboring pixels.
+    fn sample(&self, escape: Option<Escape>, spacing: f64) -> Sample {
+        match escape {
+            None => Sample::INTERIOR,
+            Some(escape) => {
+                let smooth = escape.smooth_count(self.formula.degree(), self.bailout);
+                Sample {
+                    count: escape.count as u32,
+                    fraction: (smooth - escape.count as f64) as f32,
+                    distance: escape
+                        .distance()
+                        .map_or(f32::INFINITY, |distance| (distance / spacing) as f32),
+                    trap: escape.trap.map_or(f32::INFINITY, |trap| trap as f32),
+                }
+            }
+        }
+    }
+
+    /// Return true if the pixel at `point` is known to be in the set without iterating.
+    fn known_interior(&self, point: Complex<f64>) -> bool {
+        self.formula == Formula::Mandelbrot
+            && self.mode == Mode::Mandelbrot
+            && in_cardioid_or_bulb(point)
+    }
+
+    /// Compute `escape_time` for each of `points`, storing the results in `escapes`. The
+    /// Mandelbrot formula iterates several points at once using the CPU's vector unit,
+    /// unless distances or traps are wanted.
+    fn escape_times(&self, points: &[Complex<f64>], escapes: &mut [Option<Escape>]) {
+        if self.formula != Formula::Mandelbrot || self.distance || self.trap.is_some() {
+            for (escape, &point) in escapes.iter_mut().zip(points) {
+                *escape = self.escape_time(point);
+            }
+            return;
+        }
+        // Only hand the vector kernel the points that actually need iterating.
+        let mut indices = Vec::with_capacity(points.len());
+        let (mut zs, mut cs) = (Vec::with_capacity(points.len()), Vec::new());
+        for (i, &point) in points.iter().enumerate() {
+            escapes[i] = None;
+            if !self.known_interior(point) {
+                let (z, c) = self.mode.orbit(point);
+                indices.push(i);
+                zs.push(z);
+                cs.push(c);
+            }
+        }
+        let mut results = vec![None; indices.len()];
+        simd::escape_times(
+            simd::Kernel::detect(),
+            &zs,
+            &cs,
+            self.limit,
+            self.bailout,
+            &mut results,
+        );
+        for (i, result) in indices.into_iter().zip(results) {
+            escapes[i] = result;
+        }
+    }
+}
+
+#[test]
+fn test_julia_mode() {
+    let julia = |c| Fractal {
+        mode: Mode::Julia(c),
+        ..Fractal::default()
+    };
+    // The Julia set for c = 0 is the unit circle.
+    assert_eq!(julia(ZERO).escape_time(Complex { re: 0.6, im: -0.7 }), None);
+    assert!(julia(ZERO)
+        .escape_time(Complex { re: 0.8, im: 0.7 })
+        .is_some());
+
+    // Every Julia set for a point in the Mandelbrot set is connected, so contains zero.
+    for c in [
+        Complex { re: -1.0, im: 0.0 },
+        Complex {
+            re: -0.122561,
+            im: 0.744862,
+        },
+    ] {
+        assert_eq!(julia(c).escape_time(ZERO), None);
+    }
+    assert_eq!(
+        Fractal::default()
+            .escape_time(Complex { re: 1.0, im: 0.0 })
+            .map(|e| e.count),
+        Some(3)
+    );
+}
+
+#[test]
+fn test_formula_escape_times() {
+    let escape = |formula, re, im| {
+        Fractal {
+            formula,
+            ..Fractal::default()
+        }
+        .escape_time(Complex { re, im })
+        .map(|e| e.count)
+    };
+    // Unlike the Mandelbrot set, the Burning Ship is not symmetric about the real axis:
+    // the small ship near -1.76 lies just below it.
+    assert_eq!(escape(Formula::BurningShip, -1.76, -0.02), None);
+    assert!(escape(Formula::BurningShip, -1.76, 0.02).is_some());
+    // The Tricorn is symmetric about the real axis and has three-fold symmetry.
+    let rotate = Complex::from_polar(1.0, 2.0 * std::f64::consts::PI / 3.0);
+    for i in 0..50 {
+        let c = Complex::from_polar(0.2 + i as f64 * 0.02, 0.1);
+        let r = c * rotate;
+        assert_eq!(
+            escape(Formula::Tricorn, c.re, c.im).is_some(),
+            escape(Formula::Tricorn, c.re, -c.im).is_some()
+        );
+        assert_eq!(
+            escape(Formula::Tricorn, c.re, c.im).is_some(),
+            escape(Formula::Tricorn, r.re, r.im).is_some()
+        );
+    }
+    // The cubic Multibrot set reaches further along the imaginary axis than the real.
+    assert_eq!(escape(Formula::Multibrot(3.0), 0.0, 0.5), None);
+    assert!(escape(Formula::Multibrot(3.0), 0.5, 0.0).is_some());
+}
+
+/// What iterating one pixel found, kept at full precision until the coloring stage maps
+/// it onto the palette.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+struct Sample {
+    /// The iteration at which the orbit escaped, or `u32::MAX` if it never did.
+    count: u32,
+    /// `Escape::smooth_count` less `count`.
+    fraction: f32,
+    /// `Escape::distance` in pixels: zero for points in the set, and infinite for points
+    /// whose distance wasn't estimated.
+    distance: f32,
+    /// `Escape::trap`, in the units of the complex plane: infinite for points in the set
+    /// and points whose orbits weren't measured against a trap.
+    trap: f32,
+}
+
+impl Sample {
+    /// The sample for a point in the set.
+    const INTERIOR: Sample = Sample {
+        count: u32::MAX,
+        fraction: 0.0,
+        distance: 0.0,
+        trap: f32::INFINITY,
+    };
+
+    fn escaped(&self) -> bool {
+        self.count != u32::MAX
+    }
+
+    /// The continuous iteration count from `Escape::smooth_count`.
+    fn smooth_count(&self) -> f64 {
+        self.count as f64 + self.fraction as f64
+    }
+}
+
+#[test]
+fn test_sample() {
+    let fractal = Fractal::default();
+    assert_eq!(fractal.sample(None, 0.01), Sample::INTERIOR);
+    assert!(!Sample::INTERIOR.escaped());
+
+    let escape = fractal.escape_time(Complex { re: 0.3, im: 0.0 }).unwrap();
+    let sample = fractal.sample(Some(escape), 0.01);
+    assert!(sample.escaped());
+    assert_eq!(sample.count as usize, escape.count);
+    assert!((sample.smooth_count() - escape.smooth_count(2.0, ESCAPE_RADIUS)).abs() < 1e-5);
+    assert_eq!(sample.distance, f32::INFINITY);
+
+    let fractal = Fractal {
+        distance: true,
+        ..fractal
+    };
+    let escape = fractal.escape_time(Complex { re: 0.3, im: 0.0 }).unwrap();
+    let sample = fractal.sample(Some(escape), 0.01);
+    assert_eq!(sample.distance, (escape.distance().unwrap() / 0.01) as f32);
+    assert_eq!(sample.trap, f32::INFINITY);
+
+    // The orbit of 1 runs 0, 1, 2, 5, escaping at 5. The starting zero isn't measured
+    // against a trap, but the value that escapes is.
+    let trapped = |re| Fractal {
+        trap: Some(Trap::Point(Complex { re, im: 0.0 })),
+        ..Fractal::default()
+    };
+    let escape = trapped(0.0)
+        .escape_time(Complex { re: 1.0, im: 0.0 })
+        .unwrap();
+    assert_eq!(escape.trap, Some(1.0));
+    let escape = trapped(4.5)
+        .escape_time(Complex { re: 1.0, im: 0.0 })
+        .unwrap();
+    assert_eq!(escape.trap, Some(0.5));
+    assert_eq!(trapped(4.5).sample(Some(escape), 0.01).trap, 0.5);
+}
+
+#[test]
+fn test_distance() {
+    // Outside the unit disk, the distance to the Julia set for c = 0 is exact: |z| - 1.
+    let julia = Fractal {
+        mode: Mode::Julia(ZERO),
+        distance: true,
+        ..Fractal::default()
+    };
+    for point in [Complex { re: 1.5, im: 0.0 }, Complex { re: 0.0, im: -1.01 }] {
+        let distance = julia.escape_time(point).unwrap().distance().unwrap();
+        let actual = point.norm() - 1.0;
+        assert!(distance > actual / 2.0 && distance < actual * 2.0);
+    }
+    // The nearest point of the Mandelbrot set to any real number below -2 is its tip.
+    let mandelbrot = Fractal {
+        distance: true,
+        ..Fractal::default()
+    };
+    let mut previous = f64::INFINITY;
+    for re in [-3.0, -2.5, -2.2] {
+        let distance = mandelbrot
+            .escape_time(Complex { re, im: 0.0 })
+            .unwrap()
+            .distance()
+            .unwrap();
+        let actual = -2.0 - re;
+        assert!(distance > actual / 2.0 && distance < actual * 2.0);
+        assert!(distance < previous);
+        previous = distance;
+    }
+    // Without tracking there is no estimate.
+    let escape = Fractal::default().escape_time(Complex { re: -2.5, im: 0.0 });
+    assert_eq!(escape.unwrap().distance(), None);
+}
+
+/// How an escape time is turned into a position along the palette.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Shading {
+    /// Use the integer iteration count, which shows distinct bands.
+    Banded,
+    /// Use the continuous iteration count from `Escape::smooth_count`.
+    Smooth,
+    /// Use the estimated distance to the set, darkening toward it, so that filaments too
+    /// thin to hold a pixel of their own still show.
+    Distance,
+    /// Draw only the set's boundary: the end of the palette for points within a pixel of
+    /// the set, and the start for everything else, including the set's interior.
+    LineArt,
+    /// Use how close the orbit came to the trap, moving along the palette as it comes
+    /// closer.
+    Trap(Trap),
+}
+
+impl Shading {
+    /// Whether this shading needs the distance estimates `Fractal::distance` asks for.
+    fn needs_distance(&self) -> bool {
+        matches!(self, Shading::Distance | Shading::LineArt)
+    }
+
+    /// The trap this shading needs orbits measured against, if any.
+    fn trap(&self) -> Option<Trap> {
+        match *self {
+            Shading::Trap(trap) => Some(trap),
+            _ => None,
+        }
+    }
+}
+
+/// How far from the trap, in the units of the complex plane, an orbit's closest approach
+/// must be to land half way along the palette.
+const TRAP_SCALE: f64 = 0.05;
+
+/// How pixels are colored: the palette, and how escape times map onto it.
+#[derive(Clone, Debug, PartialEq)]
+struct Coloring {
+    palette: Palette,
+    shading: Shading,
+    /// For histogram coloring, the histogram of the image's escape counts, which spaces
+    /// them along the palette in place of the iteration limit. It starts out empty, and
+    /// `for_image` fills it in once the image has been rendered.
+    histogram: Option<Histogram>,
+}
+
+impl Default for Coloring {
+    /// The tool's original coloring: banded shades of gray.
+    fn default() -> Coloring {
+        Coloring {
+            palette: Palette::gray(),
+            shading: Shading::Banded,
+            histogram: None,
+        }
+    }
+}
+
+impl Coloring {
+    /// The number of bytes each pixel occupies.
+    fn channels(&self) -> usize {
+        self.palette.channels()
+    }
+
+    /// The coloring for the image made up of `samples`: this one, with the histogram of
+    /// `samples` if it uses histogram coloring.
+    fn for_image(&self, samples: &[Sample]) -> Coloring {
+        Coloring {
+            histogram: self.histogram.as_ref().map(|_| Histogram::of(samples)),
+            ..self.clone()
+        }
+    }
+
+    /// Map a sample iterated with the given `limit` to a position in `0.0..=1.0` along the
+    /// palette, or `None` for points in the set.
+    fn position(&self, sample: Sample, limit: usize) -> Option<f64> {
+        if self.shading == Shading::LineArt {
+            let boundary = sample.escaped() && sample.distance < 1.0;
+            return Some(if boundary { 1.0 } else { 0.0 });
+        }
+        if !sample.escaped() {
+            return None;
+        }
+        let count = match self.shading {
+            Shading::Banded => sample.count as f64,
+            Shading::Smooth => sample.smooth_count(),
+            // Half way along the palette one pixel out, and a tenth of the way at nine.
+            Shading::Distance => return Some(1.0 / (1.0 + sample.distance as f64)),
+            Shading::Trap(_) => return Some(1.0 / (1.0 + sample.trap as f64 / TRAP_SCALE)),
+            Shading::LineArt => unreachable!(),
+        };
+        let position = match &self.histogram {
+            Some(histogram) => histogram.position(count),
+            None => count / limit as f64,
+        };
+        Some(position.min(1.0))
+    }
+
+    /// Color `samples`, iterated with the given `limit`, returning `channels()` bytes per
+    /// sample.
+    fn colorize(&self, samples: &[Sample], limit: usize) -> Vec<u8> {
+        let channels = self.channels();
+        let mut pixels = vec![0; samples.len() * channels];
+        for (pixel, &sample) in pixels.chunks_mut(channels).zip(samples) {
+            let t = self.position(sample, limit);
+            self.palette.paint(pixel, t);
+        }
+        pixels
+    }
+}
+
+#[test]
+fn test_colorize() {
+    let escaped = |count| Sample {
+        count,
+        fraction: 0.9,
+        distance: count as f32 / 10.0,
+        trap: count as f32 / 100.0,
+    };
+    let samples = [Sample::INTERIOR, escaped(0), escaped(51), escaped(1000)];
+    let coloring = Coloring::default();
+    assert_eq!(coloring.colorize(&samples, 255), [0, 255, 204, 0]);
+    // Raising the limit spreads the same counts over more of the palette.
+    assert_eq!(coloring.colorize(&samples, 1020), [0, 255, 242, 5]);
+    let smooth = Coloring {
+        shading: Shading::Smooth,
+        ..Coloring::default()
+    };
+    assert_eq!(smooth.colorize(&samples, 255)[2], 203);
+    let distance = Coloring {
+        shading: Shading::Distance,
+        ..Coloring::default()
+    };
+    assert_eq!(distance.colorize(&samples, 255), [0, 0, 213, 252]);
+    let line_art = Coloring {
+        shading: Shading::LineArt,
+        ..Coloring::default()
+    };
+    assert_eq!(line_art.colorize(&samples, 255), [255, 0, 255, 255]);
+    let trap = Coloring {
+        shading: Shading::Trap(Trap::Point(ZERO)),
+        ..Coloring::default()
+    };
+    assert_eq!(trap.colorize(&samples, 255), [0, 0, 232, 254]);
+    // Histogram coloring spaces the three counts evenly, whatever the limit.
+    let histogram = Coloring {
+        histogram: Some(Histogram::default()),
+        ..Coloring::default()
+    }
+    .for_image(&samples);
+    assert_eq!(histogram.colorize(&samples, 255), [0, 170, 85, 0]);
+    assert_eq!(histogram.colorize(&samples, 1020), [0, 170, 85, 0]);
+}
+
 fn parse_pair<T: FromStr>(s: &str, separator: char) -> Option<(T, T)> {
     match s.find(separator) {
         None => None,
@@ -37,10 +917,7 @@ fn test_parser_pair() {
 }
 
 fn parse_complex(s: &str) -> Option<Complex<f64>> {
-    match parse_pair(s, ',') {
-        Some((re, im)) => Some(Complex { re, im }),
-        None => None,
-    }
+    parse_pair(s, ',').map(|(re, im)| Complex { re, im })
 }
 
 #[test]
@@ -55,6 +932,28 @@ fn test_parse_complex() {
     assert_eq!(parse_complex(",-0.0625"), None);
 }
 
+/// Parse a complex number like `parse_complex`, but keeping every digit given.
+fn parse_fixed_complex(s: &str) -> Option<FixedComplex> {
+    let (re, im) = parse_pair::<Fixed>(s, ',')?;
+    let bits = re.bits().max(im.bits());
+    Some(FixedComplex {
+        re: re.with_bits(bits),
+        im: im.with_bits(bits),
+    })
+}
+
+#[test]
+fn test_parse_fixed_complex() {
+    assert_eq!(
+        parse_fixed_complex("1.25,-0.0625").map(|z| z.to_complex()),
+        Some(Complex {
+            re: 1.25,
+            im: -0.0625
+        })
+    );
+    assert_eq!(parse_fixed_complex(",-0.0625"), None);
+}
+
 fn pixel_to_point(
     bounds: (usize, usize),
     pixel: (usize, usize),
@@ -87,77 +986,1046 @@ fn test_pixel_to_point() {
     )
 }
 
+/// Render `view` of `fractal` into a buffer of samples, one per pixel, using `threads`
+/// worker threads.
 fn render(
-    pixels: &mut [u8],
+    samples: &mut [Sample],
     bounds: (usize, usize),
-    upper_left: Complex<f64>,
-    lower_right: Complex<f64>,
+    view: &View,
+    fractal: &Fractal,
+    threads: usize,
 ) {
-    assert!(pixels.len() == bounds.0 * bounds.1);
-    let threads = 8;
-    let rows_per_band = bounds.1 / threads + 1;
-
-    {
-        let bands: Vec<&mut [u8]> = pixels.chunks_mut(rows_per_band * bounds.0).collect();
-        crossbeam::scope(|spawner| {
-            for (i, band) in bands.into_iter().enumerate() {
-                let top = rows_per_band * i;
-                let height = band.len() / bounds.0;
-                let band_bounds = (bounds.0, height);
-                let band_upper_left = pixel_to_point(bounds, (0, top), upper_left, lower_right);
-                let band_lower_right =
-                    pixel_to_point(bounds, (bounds.0, top + height), upper_left, lower_right);
-                spawner.spawn(move |_| {
-                    render_band(band, band_bounds, band_upper_left, band_lower_right);
-                });
-            }
-        })
-        .unwrap()
-    }
+    let (upper_left, lower_right) = (view.upper_left, view.lower_right);
+    let spacing = deep::pixel_spacing(bounds, upper_left, lower_right);
+    let tiles = tiles(bounds, (TILE_SIZE, TILE_SIZE));
+    render_tiles(samples, bounds, &tiles, threads, |buffer, tile| {
+        let tile_upper_left =
+            pixel_to_point(bounds, (tile.left, tile.top), upper_left, lower_right);
+        let tile_lower_right = pixel_to_point(
+            bounds,
+            (tile.left + tile.width, tile.top + tile.height),
+            upper_left,
+            lower_right,
+        );
+        render_band(
+            buffer,
+            (tile.width, tile.height),
+            tile_upper_left,
+            tile_lower_right,
+            view,
+            fractal,
+            spacing,
+        );
+    });
 }
 
+/// Render the rectangle of `view` between the given corners, turning each point by the
+/// view's rotation. `spacing` is the distance between neighboring pixels.
 fn render_band(
-    pixels: &mut [u8],
+    samples: &mut [Sample],
     bounds: (usize, usize),
     upper_left: Complex<f64>,
     lower_right: Complex<f64>,
+    view: &View,
+    fractal: &Fractal,
+    spacing: f64,
 ) {
-    for row in 0..bounds.1 {
-        for column in 0..bounds.0 {
-            let point = pixel_to_point(bounds, (column, row), upper_left, lower_right);
-            pixels[row * bounds.0 + column] = match escape_time(point, 255) {
-                None => 0,
-                Some(count) => 255 - count as u8,
-            }
+    let mut escapes = vec![None; bounds.0];
+    for (row, row_samples) in samples.chunks_mut(bounds.0).enumerate() {
+        let points: Vec<Complex<f64>> = (0..bounds.0)
+            .map(|column| pixel_to_point(bounds, (column, row), upper_left, lower_right))
+            .map(|point| view.rotate(point))
+            .collect();
+        fractal.escape_times(&points, &mut escapes);
+        for (sample, &escape) in row_samples.iter_mut().zip(&escapes) {
+            *sample = fractal.sample(escape, spacing);
         }
     }
 }
 
+/// Compare how well the tile queue keeps workers busy against the original scheme of one
+/// horizontal band per worker, on a view whose top half lies inside the set and so costs
+/// far more than its bottom half. Run with `cargo test --release -- --ignored --nocapture`.
+#[test]
+#[ignore]
+fn bench_tile_utilization() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+
+    let bounds = (1024, 768);
+    let view = View::corners("-0.6,0.4", "0.2,-0.2").unwrap();
+    let (upper_left, lower_right) = (view.upper_left, view.lower_right);
+    let fractal = Fractal::default();
+    let threads = tiles::default_threads().max(2);
+    let layouts = [
+        ("bands", tiles(bounds, (bounds.0, bounds.1 / threads + 1))),
+        ("tiles", tiles(bounds, (TILE_SIZE, TILE_SIZE))),
+    ];
+    let mut utilizations = Vec::new();
+    for (name, layout) in &layouts {
+        let busy_nanos = AtomicU64::new(0);
+        let mut samples = vec![Sample::default(); bounds.0 * bounds.1];
+        let start = Instant::now();
+        render_tiles(&mut samples, bounds, layout, threads, |buffer, tile| {
+            let tile_start = Instant::now();
+            let tile_upper_left =
+                pixel_to_point(bounds, (tile.left, tile.top), upper_left, lower_right);
+            let tile_lower_right = pixel_to_point(
+                bounds,
+                (tile.left + tile.width, tile.top + tile.height),
+                upper_left,
+                lower_right,
+            );
+            render_band(
+                buffer,
+                (tile.width, tile.height),
+                tile_upper_left,
+                tile_lower_right,
+                &view,
+                &fractal,
+                deep::pixel_spacing(bounds, upper_left, lower_right),
+            );
+            busy_nanos.fetch_add(tile_start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        });
+        let wall = start.elapsed();
+        let utilization =
+            busy_nanos.into_inner() as f64 / (wall.as_nanos() as f64 * threads as f64);
+        println!(
+            "{}: {:?} wall clock, {:.0}% utilization of {} threads",
+            name,
+            wall,
+            utilization * 100.0,
+            threads
+        );
+        utilizations.push(utilization);
+    }
+    if tiles::default_threads() >= threads {
+        assert!(utilizations[1] > utilizations[0]);
+    }
+}
+
 fn write_image(
     filename: &str,
     pixels: &[u8],
     bounds: (usize, usize),
+    color_type: ColorType,
 ) -> Result<(), std::io::Error> {
     let output = File::create(filename)?;
     let encode = PNGEncoder::new(output);
-    encode.encode(pixels, bounds.0 as u32, bounds.1 as u32, ColorType::Gray(8))?;
+    encode.encode(pixels, bounds.0 as u32, bounds.1 as u32, color_type)?;
     Ok(())
 }
 
+/// Images with more samples than this are streamed: rendered and written a band at a time
+/// rather than all at once. The samples alone would take 1 GiB.
+const STREAM_THRESHOLD: usize = 1 << 26;
+
+/// The number of samples each band of a streamed image aims to hold.
+const BAND_SAMPLES: usize = 1 << 22;
+
+#[derive(Debug)]
+struct Arguments {
+    filename: String,
+    format: ImageFormat,
+    bounds: (usize, usize),
+    view: View,
+    fractal: Fractal,
+    coloring: Coloring,
+    threads: usize,
+    /// Each pixel is the average of `supersample` × `supersample` samples.
+    supersample: usize,
+    /// If set, render a sequence of frames rather than a single image.
+    animation: Option<Animation>,
+    /// Render and write the image a band at a time, however small it is.
+    stream: bool,
+    /// If set, render a tile pyramid with this many zoom levels after the first into the
+    /// directory `filename`, rather than a single image.
+    pyramid: Option<usize>,
+    /// If set, serve tiles over HTTP at this address rather than writing any files.
+    serve: Option<String>,
+    /// How many rendered tiles the server keeps in memory.
+    cache_tiles: usize,
+    /// If set, draw a Buddhabrot rather than coloring each pixel by its own escape time.
+    buddhabrot: Option<Buddhabrot>,
+    /// If set, draw the basins of Newton's method for this polynomial instead of a fractal
+    /// of escape times.
+    newton: Option<Polynomial>,
+    /// If set, write a scene file describing the image here before rendering it.
+    dump_scene: Option<String>,
+}
+
+fn print_usage(program: &str) {
+    eprintln!("Usage: mandelbrot [OPTIONS] FILE PIXELS UPPERLEFT LOWERRIGHT");
+    eprintln!("       mandelbrot [OPTIONS] --center RE,IM [--zoom Z | --radius R] FILE PIXELS");
+    eprintln!("       mandelbrot [OPTIONS] --scene SCENE [FILE]");
+    eprintln!(
+        "       mandelbrot recolor [--palette, --gradient, --smooth, --histogram, --format] RAW FILE"
+    );
+    eprintln!(
+        "Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
+        program
+    );
+    eprintln!("Options:");
+    eprintln!("  --center RE,IM    center the view on RE,IM instead of giving its corners");
+    eprintln!("  --zoom Z          magnification around --center; 1 shows radius 2 (default)");
+    eprintln!("  --radius R        distance from --center to the nearest edge of the image");
+    eprintln!("  --rotate DEGREES  turn the view counterclockwise about its center");
+    eprintln!("  --frames N        render N numbered frames zooming to the --to-* view");
+    eprintln!("  --to-center RE,IM center of the last frame (default: the first frame's)");
+    eprintln!("  --to-zoom Z       zoom of the last frame, as --zoom");
+    eprintln!("  --to-radius R     radius of the last frame, as --radius");
+    eprintln!("  --to-rotate DEG   rotation of the last frame, as --rotate");
+    eprintln!("  --keyframes FILE  render the frames of an animation given by a keyframe file");
+    eprintln!("  --resume          skip animation frames that already exist");
+    eprintln!("  --delay MS        frame delay for .gif and .apng animations (default: 40)");
+    eprintln!("  --loops N         times .gif and .apng animations play, 0 for ever (default)");
+    eprintln!(
+        "  --palette NAME    built-in color palette: {}",
+        Palette::builtin_names().join(", ")
+    );
+    eprintln!("  --gradient FILE   load color stops from a gradient file");
+    eprintln!("  --formula NAME    mandelbrot (default), burning-ship, tricorn or multibrot:D");
+    eprintln!("  --julia RE,IM     draw the Julia set for the constant RE,IM");
+    eprintln!("  --smooth          use continuous iteration counts to avoid banding");
+    eprintln!("  --distance        shade by estimated distance to the set, showing thin filaments");
+    eprintln!("  --line-art        draw only the set's boundary, as lines on a plain background");
+    eprintln!("  --histogram       spread colors evenly over the image's escape counts");
+    eprintln!("  --trap SHAPE      color by how close each orbit comes to point:RE,IM,");
+    eprintln!("                    line:RE,IM,DEGREES or circle:RE,IM,RADIUS");
+    eprintln!("  --max-iter N      iterations before a point counts as inside (default: 255)");
+    eprintln!("  --bailout R       escape radius, greater than 1 (default: 2)");
+    eprintln!("  --supersample N   average N×N samples per pixel to smooth edges, N up to 16");
+    eprintln!("                    (default: 1)");
+    eprintln!("  --threads N       number of worker threads (default: one per CPU)");
+    eprintln!("  --format NAME     png, png16, pnm or raw (default: from FILE's extension)");
+    eprintln!("  --stream          render and write the image a band at a time to save memory");
+    eprintln!(
+        "  --pyramid Z       write 256×256 tiles for zoom levels 0 to Z into directory FILE,"
+    );
+    eprintln!("                    taking no PIXELS argument");
+    eprintln!("  --serve ADDRESS   serve tiles and a viewer page over HTTP, taking no FILE or");
+    eprintln!("                    PIXELS arguments; ADDRESS is like 127.0.0.1:8080");
+    eprintln!("  --cache N         number of tiles --serve keeps in memory (default: 2048)");
+    eprintln!("  --buddhabrot N    trace the orbits of N random points, such as 1e7, and draw");
+    eprintln!("                    how often each pixel is visited, up to --max-iter steps");
+    eprintln!("  --anti            with --buddhabrot, trace the points that don't escape instead");
+    eprintln!("  --nebula R,G,B    with --buddhabrot, draw red, green and blue with these limits");
+    eprintln!("  --seed N          with --buddhabrot, seed for the random points (default: 0)");
+    eprintln!("  --newton A:B:...  color points by the root of the polynomial Az^n + Bz^(n-1)...");
+    eprintln!("                    Newton's method takes them to; each coefficient is RE,IM");
+    eprintln!("  --scene FILE      read the arguments and options from a TOML or JSON scene");
+    eprintln!("                    file; later options override it, and FILE its output");
+    eprintln!("  --dump-scene FILE write a scene file that renders this image again exactly");
+}
+
+fn usage_error(program: &str, message: &str) -> ! {
+    print_usage(program);
+    eprintln!("Error: {}", message);
+    std::process::exit(1);
+}
+
+/// Handle `arg` if it is one of the options that choose how samples are colored and
+/// written, shared by rendering and `recolor`, calling `value` to take its value. Return
+/// whether it was.
+fn parse_coloring_option(
+    program: &str,
+    arg: &str,
+    value: &mut dyn FnMut(&str) -> String,
+    coloring: &mut Coloring,
+    format: &mut Option<ImageFormat>,
+) -> bool {
+    match arg {
+        "--palette" => {
+            let name = value("--palette");
+            coloring.palette = Palette::builtin(&name)
+                .unwrap_or_else(|| usage_error(program, &format!("unknown palette '{}'", name)));
+        }
+        "--gradient" => {
+            let filename = value("--gradient");
+            coloring.palette = Palette::load(&filename).unwrap_or_else(|e| {
+                usage_error(
+                    program,
+                    &format!("failed to read gradient '{}': {}", filename, e),
+                )
+            });
+        }
+        "--smooth" => coloring.shading = Shading::Smooth,
+        "--distance" => coloring.shading = Shading::Distance,
+        "--line-art" => coloring.shading = Shading::LineArt,
+        "--trap" => {
+            coloring.shading = value("--trap")
+                .parse()
+                .map(Shading::Trap)
+                .unwrap_or_else(|e: String| usage_error(program, &e));
+        }
+        "--histogram" => coloring.histogram = Some(Histogram::default()),
+        "--format" => {
+            let name = value("--format");
+            *format = Some(ImageFormat::from_name(&name).unwrap_or_else(|| {
+                usage_error(program, &format!("unknown output format '{}'", name))
+            }));
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Exit with a usage error if the coloring options don't go together, or `format` can't
+/// hold images colored with `coloring`.
+fn check_format(program: &str, format: ImageFormat, coloring: &Coloring) {
+    if coloring.histogram.is_some() {
+        if coloring.shading.needs_distance() {
+            usage_error(
+                program,
+                "--histogram spreads out escape counts, not distances",
+            );
+        }
+        if coloring.shading.trap().is_some() {
+            usage_error(
+                program,
+                "--histogram spreads out escape counts, not trap distances",
+            );
+        }
+    }
+    match format {
+        ImageFormat::Png => {}
+        ImageFormat::Png16 => {
+            if coloring.palette != Palette::gray() {
+                usage_error(program, "16-bit PNG output is always gray; drop --palette");
+            }
+        }
+        ImageFormat::Pnm => {
+            if coloring.channels() == 4 {
+                usage_error(program, "PPM output needs a palette without transparency");
+            }
+        }
+        ImageFormat::Raw => {
+            if coloring.shading.needs_distance() {
+                usage_error(program, "raw sample files don't keep distance estimates");
+            }
+            if coloring.shading.trap().is_some() {
+                usage_error(program, "raw sample files don't keep trap distances");
+            }
+        }
+    }
+}
+
+fn parse_args() -> Arguments {
+    // A queue, so that a scene file's options can be put in front of those that follow it.
+    let mut args: VecDeque<String> = env::args().collect();
+    let program = args.pop_front().unwrap_or_else(|| "mandelbrot".to_string());
+    let mut coloring = Coloring::default();
+    let mut fractal = Fractal::default();
+    let mut threads = tiles::default_threads();
+    let mut supersample = 1;
+    let mut center = None;
+    let mut radius = None;
+    let mut rotation = 0.0;
+    let mut frames = None;
+    let mut to_center = None;
+    let mut to_radius = None;
+    let mut to_rotation = None;
+    let mut keyframes = None;
+    let mut resume = false;
+    let mut format = None;
+    let mut stream = false;
+    let mut pyramid = None;
+    let mut serve = None;
+    let mut cache_tiles = serve::DEFAULT_CACHE_TILES;
+    let mut buddhabrot_samples = None;
+    let mut anti = false;
+    let mut nebula = None;
+    let mut seed = None;
+    let mut newton = None;
+    let mut scene = None;
+    let mut dump_scene = None;
+    let mut chose_palette = false;
+    let mut playback = Playback {
+        delay: 40,
+        loops: 0,
+    };
+    let mut positional = Vec::new();
+    while let Some(arg) = args.pop_front() {
+        let mut value = |name: &str| {
+            args.pop_front()
+                .unwrap_or_else(|| usage_error(&program, &format!("{} needs a value", name)))
+        };
+        if parse_coloring_option(&program, &arg, &mut value, &mut coloring, &mut format) {
+            chose_palette |= arg == "--palette" || arg == "--gradient";
+            continue;
+        }
+        match arg.as_str() {
+            "--formula" => {
+                fractal.formula = value("--formula")
+                    .parse()
+                    .unwrap_or_else(|e: String| usage_error(&program, &e));
+            }
+            "--julia" => {
+                let c = value("--julia");
+                fractal.mode = Mode::Julia(parse_complex(&c).unwrap_or_else(|| {
+                    usage_error(&program, &format!("error parsing Julia constant '{}'", c))
+                }));
+            }
+            "--max-iter" => {
+                // Counts are stored as u32, with u32::MAX marking points in the set.
+                fractal.limit = match value("--max-iter").parse::<u32>() {
+                    Ok(n) if n > 0 => n as usize,
+                    _ => usage_error(&program, "--max-iter needs a positive 32-bit number"),
+                };
+            }
+            "--bailout" => {
+                fractal.bailout = match value("--bailout").parse::<f64>() {
+                    Ok(r) if r > 1.0 && r.is_finite() => r,
+                    _ => usage_error(&program, "--bailout needs a number greater than 1"),
+                };
+            }
+            "--center" => {
+                let text = value("--center");
+                center = Some(parse_fixed_complex(&text).unwrap_or_else(|| {
+                    usage_error(&program, &format!("error parsing center '{}'", text))
+                }));
+            }
+            "--zoom" => {
+                radius = match value("--zoom").parse::<f64>() {
+                    Ok(zoom) if zoom > 0.0 && zoom.is_finite() => Some(2.0 / zoom),
+                    _ => usage_error(&program, "--zoom needs a positive number"),
+                };
+            }
+            "--radius" => {
+                radius = match value("--radius").parse::<f64>() {
+                    Ok(r) if r > 0.0 && r.is_finite() => Some(r),
+                    _ => usage_error(&program, "--radius needs a positive number"),
+                };
+            }
+            "--rotate" => {
+                rotation = match value("--rotate").parse::<f64>() {
+                    Ok(degrees) if degrees.is_finite() => degrees.to_radians(),
+                    _ => usage_error(&program, "--rotate needs a number of degrees"),
+                };
+            }
+            "--frames" => {
+                frames = match value("--frames").parse() {
+                    Ok(n) if n > 1 => Some(n),
+                    _ => usage_error(&program, "--frames needs a number greater than 1"),
+                };
+            }
+            "--to-center" => {
+                let text = value("--to-center");
+                to_center = Some(parse_fixed_complex(&text).unwrap_or_else(|| {
+                    usage_error(&program, &format!("error parsing center '{}'", text))
+                }));
+            }
+            "--to-zoom" => {
+                to_radius = match value("--to-zoom").parse::<f64>() {
+                    Ok(zoom) if zoom > 0.0 && zoom.is_finite() => Some(2.0 / zoom),
+                    _ => usage_error(&program, "--to-zoom needs a positive number"),
+                };
+            }
+            "--to-radius" => {
+                to_radius = match value("--to-radius").parse::<f64>() {
+                    Ok(r) if r > 0.0 && r.is_finite() => Some(r),
+                    _ => usage_error(&program, "--to-radius needs a positive number"),
+                };
+            }
+            "--to-rotate" => {
+                to_rotation = match value("--to-rotate").parse::<f64>() {
+                    Ok(degrees) if degrees.is_finite() => Some(degrees.to_radians()),
+                    _ => usage_error(&program, "--to-rotate needs a number of degrees"),
+                };
+            }
+            "--keyframes" => {
+                let filename = value("--keyframes");
+                keyframes = Some(animation::load_keyframes(&filename).unwrap_or_else(|e| {
+                    usage_error(
+                        &program,
+                        &format!("failed to read keyframes '{}': {}", filename, e),
+                    )
+                }));
+            }
+            "--resume" => resume = true,
+            "--delay" => {
+                playback.delay = value("--delay").parse().unwrap_or_else(|_| {
+                    usage_error(
+                        &program,
+                        "--delay needs a number of milliseconds up to 65535",
+                    )
+                });
+            }
+            "--loops" => {
+                playback.loops = value("--loops").parse().unwrap_or_else(|_| {
+                    usage_error(&program, "--loops needs a number up to 65535")
+                });
+            }
+            "--supersample" => {
+                supersample = match value("--supersample").parse() {
+                    Ok(n) if (1..=supersample::MAX_FACTOR).contains(&n) => n,
+                    _ => usage_error(
+                        &program,
+                        &format!(
+                            "--supersample needs a number from 1 to {}",
+                            supersample::MAX_FACTOR
+                        ),
+                    ),
+                };
+            }
+            "--stream" => stream = true,
+            "--pyramid" => {
+                pyramid = match value("--pyramid").parse() {
+                    Ok(n) if n <= pyramid::MAX_LEVEL => Some(n),
+                    _ => usage_error(
+                        &program,
+                        &format!("--pyramid needs a zoom level up to {}", pyramid::MAX_LEVEL),
+                    ),
+                };
+            }
+            "--serve" => serve = Some(value("--serve")),
+            "--cache" => {
+                cache_tiles = value("--cache")
+                    .parse()
+                    .unwrap_or_else(|_| usage_error(&program, "--cache needs a number of tiles"));
+            }
+            "--buddhabrot" => {
+                // Accept counts like 1e7: they run long.
+                buddhabrot_samples = match value("--buddhabrot").parse::<f64>() {
+                    Ok(n) if n >= 1.0 && n <= u64::MAX as f64 => Some(n as u64),
+                    _ => usage_error(&program, "--buddhabrot needs a positive number of points"),
+                };
+            }
+            "--anti" => anti = true,
+            "--nebula" => {
+                let text = value("--nebula");
+                let limits: Option<Vec<usize>> = text
+                    .split(',')
+                    .map(|limit| match limit.parse::<u32>() {
+                        Ok(n) if n > 0 => Some(n as usize),
+                        _ => None,
+                    })
+                    .collect();
+                nebula = match limits {
+                    Some(limits) if limits.len() == 3 => Some(limits),
+                    _ => usage_error(
+                        &program,
+                        &format!("--nebula needs three positive limits, not '{}'", text),
+                    ),
+                };
+            }
+            "--seed" => {
+                seed = Some(
+                    value("--seed")
+                        .parse()
+                        .unwrap_or_else(|_| usage_error(&program, "--seed needs a 64-bit number")),
+                );
+            }
+            "--newton" => {
+                newton = Some(
+                    value("--newton")
+                        .parse::<Polynomial>()
+                        .unwrap_or_else(|e| usage_error(&program, &e)),
+                );
+            }
+            "--scene" => {
+                let filename = value("--scene");
+                let loaded = Scene::load(&filename).unwrap_or_else(|e| {
+                    usage_error(
+                        &program,
+                        &format!("failed to read scene '{}': {}", filename, e),
+                    )
+                });
+                if let Some(palette) = loaded.stops() {
+                    coloring.palette = palette;
+                    chose_palette = true;
+                }
+                for option in loaded.options().into_iter().rev() {
+                    args.push_front(option);
+                }
+                scene = Some(loaded);
+            }
+            "--dump-scene" => dump_scene = Some(value("--dump-scene")),
+            "--threads" => {
+                threads = match value("--threads").parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => usage_error(&program, "--threads needs a positive number"),
+                };
+            }
+            _ if arg.starts_with("--") => {
+                usage_error(&program, &format!("unknown option '{}'", arg))
+            }
+            _ => positional.push(arg),
+        }
+    }
+    if let Some(scene) = &scene {
+        // A FILE argument writes the scene's image somewhere else.
+        if positional.len() > 1 {
+            usage_error(&program, "--scene takes no arguments but FILE");
+        }
+        positional = scene.arguments(positional.pop());
+    }
+    // A pyramid's size follows from its levels, so it takes no PIXELS argument, and a
+    // server sizes its tiles itself and writes no file.
+    let takes_file = serve.is_none();
+    let takes_pixels = takes_file && pyramid.is_none();
+    let takes_corners = center.is_none() && keyframes.is_none();
+    let expected = takes_file as usize + takes_pixels as usize + 2 * takes_corners as usize;
+    if positional.len() != expected {
+        usage_error(
+            &program,
+            &format!("expected {} arguments, got {}", expected, positional.len()),
+        );
+    }
+    if radius.is_some() && center.is_none() {
+        usage_error(&program, "--zoom and --radius need --center");
+    }
+    let mut positional = positional.into_iter();
+    let filename = if takes_file {
+        positional.next().unwrap()
+    } else {
+        String::new()
+    };
+    let bounds: (usize, usize) = match (pyramid, &serve) {
+        (Some(levels), _) => (pyramid::level_size(levels), pyramid::level_size(levels)),
+        (None, Some(_)) => (pyramid::TILE_SIZE, pyramid::TILE_SIZE),
+        (None, None) => match parse_pair(&positional.next().unwrap(), 'x') {
+            Some((width, height)) if width > 0 && height > 0 => (width, height),
+            _ => usage_error(&program, "error parsing image dimensions"),
+        },
+    };
+    if pyramid.is_some() && serve.is_some() {
+        usage_error(&program, "--pyramid and --serve can't be combined");
+    }
+    if (pyramid.is_some() || serve.is_some()) && (frames.is_some() || keyframes.is_some()) {
+        usage_error(
+            &program,
+            "--pyramid and --serve can't be combined with an animation",
+        );
+    }
+    let has_end = to_center.is_some() || to_radius.is_some() || to_rotation.is_some();
+    let has_start = center.is_some() || radius.is_some() || rotation != 0.0;
+    if keyframes.is_some() && (frames.is_some() || has_start || has_end) {
+        usage_error(
+            &program,
+            "--keyframes replaces the other view and animation options",
+        );
+    }
+    if has_end && frames.is_none() {
+        usage_error(
+            &program,
+            "--to-center, --to-zoom, --to-radius and --to-rotate need --frames",
+        );
+    }
+    let format = format.unwrap_or_else(|| ImageFormat::from_filename(&filename));
+    check_format(&program, format, &coloring);
+    fractal.distance = coloring.shading.needs_distance();
+    fractal.trap = coloring.shading.trap();
+    // Tiles and bands are colored as they are rendered, before the rest of the image exists.
+    let samples = bounds
+        .0
+        .checked_mul(bounds.1)
+        .and_then(|pixels| pixels.checked_mul(supersample * supersample))
+        .unwrap_or_else(|| usage_error(&program, "image dimensions are too large"));
+    if coloring.histogram.is_some()
+        && (stream || samples > STREAM_THRESHOLD || pyramid.is_some() || serve.is_some())
+    {
+        usage_error(
+            &program,
+            "--histogram needs the whole image at once; for large images, write a raw file \
+             and recolor it",
+        );
+    }
+    if (pyramid.is_some() || serve.is_some()) && format != ImageFormat::Png {
+        usage_error(&program, "pyramid and server tiles are always PNG");
+    }
+    if stream && (pyramid.is_some() || serve.is_some()) {
+        usage_error(
+            &program,
+            "--stream applies to single images, not --pyramid or --serve tiles",
+        );
+    }
+    let buddhabrot = buddhabrot_samples.map(|samples| Buddhabrot {
+        samples,
+        anti,
+        limits: nebula.clone().unwrap_or_else(|| vec![fractal.limit]),
+        seed: seed.unwrap_or(buddhabrot::DEFAULT_SEED),
+    });
+    if buddhabrot.is_none() && (anti || nebula.is_some() || seed.is_some()) {
+        usage_error(&program, "--anti, --nebula and --seed need --buddhabrot");
+    }
+    if buddhabrot.is_some() {
+        // The image counts orbits, not escape times, so it has nothing to color.
+        if coloring != Coloring::default() || format != ImageFormat::Png {
+            usage_error(
+                &program,
+                "--buddhabrot draws a PNG of its own, without coloring options",
+            );
+        }
+        if fractal.mode != Mode::Mandelbrot {
+            usage_error(
+                &program,
+                "--buddhabrot traces points c, so it can't take --julia",
+            );
+        }
+    }
+    if newton.is_some() {
+        if buddhabrot.is_some() {
+            usage_error(&program, "--newton and --buddhabrot can't be combined");
+        }
+        // Basins are colored by root, so only the palette applies.
+        if coloring.shading != Shading::Banded
+            || coloring.histogram.is_some()
+            || format != ImageFormat::Png
+        {
+            usage_error(
+                &program,
+                "--newton draws a PNG of its own, taking no coloring options but --palette",
+            );
+        }
+        if fractal.formula != Formula::Mandelbrot || fractal.mode != Mode::Mandelbrot {
+            usage_error(
+                &program,
+                "--newton iterates its own polynomial, so it can't take --formula or --julia",
+            );
+        }
+        // Shades of gray can't tell the roots apart, so the default is a colorful palette.
+        if !chose_palette {
+            coloring.palette = Palette::builtin("rainbow").unwrap();
+        }
+    }
+    if (buddhabrot.is_some() || newton.is_some())
+        && (stream
+            || pyramid.is_some()
+            || serve.is_some()
+            || frames.is_some()
+            || keyframes.is_some())
+    {
+        usage_error(
+            &program,
+            "--buddhabrot and --newton draw a single image, without --stream, --pyramid, \
+             --serve or an animation",
+        );
+    }
+    if dump_scene.is_some()
+        && (buddhabrot.is_some()
+            || newton.is_some()
+            || pyramid.is_some()
+            || serve.is_some()
+            || frames.is_some()
+            || keyframes.is_some())
+    {
+        usage_error(
+            &program,
+            "--dump-scene describes a single image, not --buddhabrot, --newton, --pyramid, \
+             --serve or an animation",
+        );
+    }
+    let view = match (&center, &keyframes) {
+        (Some(center), _) => View::centered(center, radius.unwrap_or(2.0), bounds),
+        (None, Some(keyframes)) => {
+            let first: &Keyframe = &keyframes[0];
+            View::centered(&first.center, first.radius, bounds)
+        }
+        (None, None) => View::corners(&positional.next().unwrap(), &positional.next().unwrap())
+            .unwrap_or_else(|| usage_error(&program, "error parsing corner points"))
+            .square_pixels(bounds),
+    };
+    let view = View { rotation, ..view };
+    let numbered = animated::Format::from_filename(&filename).is_none();
+    if resume && !(numbered && (frames.is_some() || keyframes.is_some())) {
+        usage_error(
+            &program,
+            "--resume only applies to animations written as numbered PNGs",
+        );
+    }
+    if stream && !numbered && (frames.is_some() || keyframes.is_some()) {
+        usage_error(
+            &program,
+            "--stream does not apply to .gif and .apng animations, which are encoded whole",
+        );
+    }
+    let animation = match (keyframes, frames) {
+        (Some(keyframes), _) => Some(Animation {
+            frames: keyframes[keyframes.len() - 1].frame + 1,
+            keyframes,
+            resume,
+            playback,
+        }),
+        (None, Some(frames)) => {
+            let start = Keyframe::from_view(0, &view);
+            let end = Keyframe {
+                frame: frames - 1,
+                center: to_center.unwrap_or_else(|| start.center.clone()),
+                radius: to_radius.unwrap_or(start.radius),
+                rotation: to_rotation.unwrap_or(start.rotation),
+            };
+            Some(Animation {
+                keyframes: vec![start, end],
+                frames,
+                resume,
+                playback,
+            })
+        }
+        (None, None) => None,
+    };
+    Arguments {
+        filename,
+        format,
+        bounds,
+        view,
+        fractal,
+        coloring,
+        threads,
+        supersample,
+        animation,
+        stream,
+        pyramid,
+        serve,
+        cache_tiles,
+        buddhabrot,
+        newton,
+        dump_scene,
+    }
+}
+
+impl Arguments {
+    /// The bounds of the grid of samples behind the image: larger than the image itself
+    /// when supersampling, which shrinks the image once it has been colored.
+    fn sample_bounds(&self) -> (usize, usize) {
+        (
+            self.bounds.0 * self.supersample,
+            self.bounds.1 * self.supersample,
+        )
+    }
+}
+
+/// Render `view` with the settings in `args`, and return a sample for each point of
+/// `args.sample_bounds()`.
+fn render_samples(args: &Arguments, view: &View) -> SampleGrid {
+    render_grid(args, view, args.sample_bounds())
+}
+
+/// Render `view` with the settings in `args` into a grid of samples of the given `bounds`,
+/// choosing the fastest method that is precise enough for the view.
+fn render_grid(args: &Arguments, view: &View, bounds: (usize, usize)) -> SampleGrid {
+    let mut samples = vec![Sample::default(); bounds.0 * bounds.1];
+    let deep_zoom = deep::needs_fixed(bounds, view.upper_left, view.lower_right);
+    if deep_zoom && !args.fractal.formula.supports_fixed() {
+        eprintln!("Warning: view is too deep for f64, but this formula has no exact form");
+    }
+    let spacing = deep::pixel_spacing(bounds, view.upper_left, view.lower_right);
+    let bits = deep::precision(bounds, view.upper_left, view.lower_right);
+    if deep_zoom && perturbation::supports(&args.fractal, spacing) {
+        perturbation::render(
+            &mut samples,
+            bounds,
+            view,
+            bits,
+            &args.fractal,
+            args.threads,
+        );
+    } else if deep_zoom && args.fractal.formula.supports_fixed() {
+        deep::render(
+            &mut samples,
+            bounds,
+            view,
+            bits,
+            &args.fractal,
+            args.threads,
+        );
+    } else {
+        render(&mut samples, bounds, view, &args.fractal, args.threads);
+    }
+    SampleGrid {
+        samples,
+        bounds,
+        supersample: args.supersample,
+        limit: args.fractal.limit,
+    }
+}
+
+/// Color `grid` with `coloring`, and return the image's pixels.
+fn color_image(grid: &SampleGrid, coloring: &Coloring) -> Vec<u8> {
+    let pixels = coloring.colorize(&grid.samples, grid.limit);
+    supersample::downsample(&pixels, grid.bounds, coloring.channels(), grid.supersample)
+}
+
+/// Render and color `view` with the settings in `args`, and return the image's pixels.
+fn render_image(args: &Arguments, view: &View) -> Vec<u8> {
+    let grid = render_samples(args, view);
+    color_image(&grid, &args.coloring.for_image(&grid.samples))
+}
+
+/// The number of rows in each band of a streamed image, for samples in a grid of the
+/// given `bounds` with the given `supersample` factor: about `BAND_SAMPLES` samples' worth,
+/// in whole pixels.
+fn band_rows(bounds: (usize, usize), supersample: usize) -> usize {
+    let rows = (BAND_SAMPLES / bounds.0).max(supersample);
+    rows - rows % supersample
+}
+
+/// Render `view` with the settings in `args`, and write it to `filename` in `args.format`.
+fn write_output(args: &Arguments, view: &View, filename: &str) -> Result<(), std::io::Error> {
+    let bounds = args.sample_bounds();
+    if args.stream || bounds.0 * bounds.1 > STREAM_THRESHOLD {
+        return stream_output(args, view, filename);
+    }
+    let grid = render_samples(args, view);
+    let coloring = args.coloring.for_image(&grid.samples);
+    if args.format == ImageFormat::Png {
+        return write_image(
+            filename,
+            &color_image(&grid, &coloring),
+            args.bounds,
+            coloring.palette.color_type(),
+        );
+    }
+    let mut writer = ImageWriter::new(
+        BufWriter::new(File::create(filename)?),
+        args.format,
+        &coloring,
+        bounds,
+        args.supersample,
+        args.fractal.limit,
+    )?;
+    writer.write_band(&grid)?;
+    writer.finish()
+}
+
+/// Like `write_output`, but render the image a band of rows at a time, writing each band
+/// out before starting the next, so memory use depends on the width of the image but not
+/// its height.
+fn stream_output(args: &Arguments, view: &View, filename: &str) -> Result<(), std::io::Error> {
+    let bounds = args.sample_bounds();
+    let mut writer = ImageWriter::new(
+        BufWriter::new(File::create(filename)?),
+        args.format,
+        &args.coloring,
+        bounds,
+        args.supersample,
+        args.fractal.limit,
+    )?;
+    let rows = band_rows(bounds, args.supersample);
+    for top in (0..bounds.1).step_by(rows) {
+        let height = rows.min(bounds.1 - top);
+        let band = view.tile(
+            bounds,
+            Tile {
+                left: 0,
+                top,
+                width: bounds.0,
+                height,
+            },
+        );
+        writer.write_band(&render_grid(args, &band, (bounds.0, height)))?;
+    }
+    writer.finish()
+}
+
+/// The settings for `recolor`, which colors a raw sample file again without rendering it.
+#[derive(Debug)]
+struct RecolorArguments {
+    input: String,
+    filename: String,
+    format: ImageFormat,
+    coloring: Coloring,
+}
+
+fn parse_recolor_args() -> RecolorArguments {
+    let mut args = env::args();
+    let program = args.next().unwrap_or_else(|| "mandelbrot".to_string());
+    args.next(); // "recolor"
+    let mut coloring = Coloring::default();
+    let mut format = None;
+    let mut positional = Vec::new();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .unwrap_or_else(|| usage_error(&program, &format!("{} needs a value", name)))
+        };
+        if parse_coloring_option(&program, &arg, &mut value, &mut coloring, &mut format) {
+            continue;
+        }
+        if arg.starts_with("--") {
+            usage_error(&program, &format!("unknown recolor option '{}'", arg));
+        }
+        positional.push(arg);
+    }
+    if positional.len() != 2 {
+        usage_error(
+            &program,
+            &format!("recolor expects 2 arguments, got {}", positional.len()),
+        );
+    }
+    let format = format.unwrap_or_else(|| ImageFormat::from_filename(&positional[1]));
+    if format == ImageFormat::Raw {
+        usage_error(&program, "recolor writes images, not raw samples");
+    }
+    // The samples come from a raw file, so they can only be colored in ways it keeps.
+    check_format(&program, ImageFormat::Raw, &coloring);
+    check_format(&program, format, &coloring);
+    RecolorArguments {
+        input: positional[0].clone(),
+        filename: positional[1].clone(),
+        format,
+        coloring,
+    }
+}
+
+/// Color the samples saved in `args.input` as `args` asks, and write the image. The
+/// samples are read and colored a band at a time, so files of any size can be recolored.
+fn recolor(args: &RecolorArguments) -> Result<(), std::io::Error> {
+    let open = || RawReader::new(BufReader::new(File::open(&args.input)?));
+    let mut reader = open()?;
+    let rows = band_rows(reader.bounds, reader.supersample);
+    let mut coloring = args.coloring.clone();
+    if let Some(histogram) = &mut coloring.histogram {
+        // Take the histogram in a pass of its own, so the samples needn't fit in memory.
+        for _ in (0..reader.bounds.1).step_by(rows) {
+            histogram.add(&reader.read_band(rows)?.samples);
+        }
+        reader = open()?;
+    }
+    let mut writer = ImageWriter::new(
+        BufWriter::new(File::create(&args.filename)?),
+        args.format,
+        &coloring,
+        reader.bounds,
+        reader.supersample,
+        reader.limit,
+    )?;
+    for _ in (0..reader.bounds.1).step_by(rows) {
+        writer.write_band(&reader.read_band(rows)?)?;
+    }
+    writer.finish()
+}
+
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 5 {
-        eprintln!("Usage: mandelbrot FILE PIXELS UPPERLEFT LOWERRIGHT");
-        eprintln!(
-            "Example: {} mandel.png 1000x750 -1.20,0.35 -1,0.20",
-            args[0]
-        );
-        std::process::exit(1);
-    }
-    let bounds = parse_pair(&args[2], 'x').expect("error parsing image dimensions");
-    let upper_left = parse_complex(&args[3]).expect("error parsing upper left corner point");
-    let lower_right = parse_complex(&args[4]).expect("error parsing lower right corner point");
-    let mut pixels = vec![0; bounds.0 * bounds.1];
-    render(&mut pixels, bounds, upper_left, lower_right);
-    write_image(&args[1], &pixels, bounds).expect("error writing PNG file");
+    if env::args().nth(1).as_deref() == Some("recolor") {
+        recolor(&parse_recolor_args()).expect("error recoloring samples");
+        return;
+    }
+    let args = parse_args();
+    if let Some(filename) = &args.dump_scene {
+        Scene::describe(&args)
+            .save(filename)
+            .expect("error writing scene file");
+    }
+    if let Some(buddhabrot) = &args.buddhabrot {
+        buddhabrot::render(&args, buddhabrot).expect("error writing Buddhabrot image");
+        return;
+    }
+    if let Some(polynomial) = &args.newton {
+        newton::render_basins(&args, polynomial).expect("error writing Newton basin image");
+        return;
+    }
+    if let Some(animation) = &args.animation {
+        animation::render_frames(&args, animation).expect("error writing animation frame");
+        return;
+    }
+    if let Some(address) = &args.serve {
+        serve::serve(&args, address).expect("error serving tiles");
+        return;
+    }
+    if let Some(levels) = args.pyramid {
+        pyramid::render_pyramid(&args, levels).expect("error writing pyramid tile");
+        return;
+    }
+    write_output(&args, &args.view, &args.filename).expect("error writing image file");
 }
@@ -0,0 +1,293 @@
+use crate::palette::Palette;
+use crate::tiles::{render_tiles, tiles, TILE_SIZE};
+use crate::view::View;
+use crate::{parse_complex, pixel_to_point, Arguments};
+use image::ColorType;
+use num::Complex;
+use std::io;
+use std::str::FromStr;
+
+/// How close Newton's method must bring a point to a root to count as having converged.
+const CONVERGED: f64 = 1e-6;
+
+/// Approximate roots closer together than this are taken to be one root. A root of
+/// multiplicity `m` is only found to within about the `m`th root of machine precision,
+/// as a cluster of `m` nearby approximations whose average is far more accurate.
+const SAME_ROOT: f64 = 1e-3;
+
+/// How many iterations darken a pixel to half the brightness of its root's color.
+const HALF_SHADE: f64 = 10.0;
+
+/// A polynomial in `z` with complex coefficients, for drawing the basins of Newton's
+/// method: which of the polynomial's roots each starting point converges to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Polynomial {
+    /// The coefficients, from that of the highest power of `z` down to the constant term.
+    coefficients: Vec<Complex<f64>>,
+    /// The distinct roots.
+    roots: Vec<Complex<f64>>,
+}
+
+/// Where Newton's method took a starting point: the index of the root it converged to,
+/// and how many iterations that took.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Basin {
+    root: usize,
+    count: usize,
+}
+
+impl Polynomial {
+    /// The polynomial with the given `coefficients`, highest power first, or `None` unless
+    /// it has a degree of at least one.
+    pub fn new(mut coefficients: Vec<Complex<f64>>) -> Option<Polynomial> {
+        let leading = coefficients.iter().position(|&a| a != crate::ZERO)?;
+        coefficients.drain(..leading);
+        if coefficients.len() < 2 {
+            return None;
+        }
+        let roots = find_roots(&coefficients);
+        Some(Polynomial {
+            coefficients,
+            roots,
+        })
+    }
+
+    /// The polynomial's value at `z`, and its derivative there.
+    fn evaluate(&self, z: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        let mut value = crate::ZERO;
+        let mut derivative = crate::ZERO;
+        for &a in &self.coefficients {
+            derivative = derivative * z + value;
+            value = value * z + a;
+        }
+        (value, derivative)
+    }
+
+    /// Apply Newton's method from `z` for at most `limit` iterations, and return where it
+    /// converged, if it did.
+    pub fn converge(&self, mut z: Complex<f64>, limit: usize) -> Option<Basin> {
+        for count in 0..limit {
+            if let Some(root) = self
+                .roots
+                .iter()
+                .position(|&root| (z - root).norm_sqr() < CONVERGED * CONVERGED)
+            {
+                return Some(Basin { root, count });
+            }
+            let (value, derivative) = self.evaluate(z);
+            if derivative == crate::ZERO {
+                return None;
+            }
+            z -= value / derivative;
+        }
+        None
+    }
+}
+
+/// Find the distinct roots of the polynomial with the given `coefficients`, using the
+/// Durand-Kerner method: improve guesses at all the roots at once, each by a Newton-like
+/// step that divides out the current guesses at the others.
+fn find_roots(coefficients: &[Complex<f64>]) -> Vec<Complex<f64>> {
+    let monic: Vec<Complex<f64>> = coefficients.iter().map(|&a| a / coefficients[0]).collect();
+    let evaluate = |z: Complex<f64>| monic.iter().fold(crate::ZERO, |value, &a| value * z + a);
+    // Powers of a number that is neither real nor on the unit circle make guesses that
+    // are distinct and not symmetric.
+    let seed = Complex { re: 0.4, im: 0.9 };
+    let mut roots: Vec<Complex<f64>> = (0..monic.len() - 1).map(|k| seed.powu(k as u32)).collect();
+    for _ in 0..1000 {
+        let mut change: f64 = 0.0;
+        for i in 0..roots.len() {
+            let denominator = (0..roots.len())
+                .filter(|&j| j != i)
+                .fold(Complex { re: 1.0, im: 0.0 }, |product, j| {
+                    product * (roots[i] - roots[j])
+                });
+            if denominator == crate::ZERO {
+                continue;
+            }
+            let step = evaluate(roots[i]) / denominator;
+            roots[i] -= step;
+            change = change.max(step.norm());
+        }
+        if change < 1e-15 {
+            break;
+        }
+    }
+    // Replace each cluster of approximations with its average.
+    let mut distinct: Vec<(Complex<f64>, usize)> = Vec::new();
+    for root in roots {
+        match distinct
+            .iter_mut()
+            .find(|(sum, n)| (*sum / *n as f64 - root).norm() < SAME_ROOT)
+        {
+            Some((sum, n)) => {
+                *sum += root;
+                *n += 1;
+            }
+            None => distinct.push((root, 1)),
+        }
+    }
+    distinct
+        .into_iter()
+        .map(|(sum, n)| sum / n as f64)
+        .collect()
+}
+
+#[cfg(test)]
+fn c(re: f64, im: f64) -> Complex<f64> {
+    Complex { re, im }
+}
+
+#[test]
+fn test_polynomial() {
+    // z³ - 1, whose roots are the cube roots of unity.
+    let cubic = Polynomial::new(vec![c(1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0), c(-1.0, 0.0)]).unwrap();
+    assert_eq!(cubic.evaluate(c(2.0, 0.0)), (c(7.0, 0.0), c(12.0, 0.0)));
+    assert_eq!(cubic.roots.len(), 3);
+    for &root in &cubic.roots {
+        assert!((root.powu(3) - c(1.0, 0.0)).norm() < 1e-12);
+    }
+    // Points near a root converge to it, quickly, and the derivative vanishes at zero.
+    let one = cubic
+        .roots
+        .iter()
+        .position(|&root| (root - c(1.0, 0.0)).norm() < 1e-9)
+        .unwrap();
+    let basin = cubic.converge(c(1.5, 0.1), 100).unwrap();
+    assert_eq!(basin.root, one);
+    assert!(basin.count > 0 && basin.count < 10);
+    assert_eq!(
+        cubic.converge(c(1.0, 0.0), 100),
+        Some(Basin {
+            root: one,
+            count: 0
+        })
+    );
+    assert_eq!(cubic.converge(c(0.0, 0.0), 100), None);
+    assert_eq!(cubic.converge(c(1.5, 0.1), 1), None);
+
+    // z³ - z² = z²(z - 1): the double root at zero counts once, and Newton's method,
+    // though it only approaches it slowly, still gets there.
+    let double =
+        Polynomial::new(vec![c(1.0, 0.0), c(-1.0, 0.0), c(0.0, 0.0), c(0.0, 0.0)]).unwrap();
+    assert_eq!(double.roots.len(), 2);
+    let zero = double.converge(c(-0.3, 0.2), 100).unwrap();
+    assert!(double.roots[zero.root].norm() < 1e-6);
+
+    // Leading zeros don't count toward the degree, and constants have no roots to find.
+    assert_eq!(
+        Polynomial::new(vec![c(0.0, 0.0), c(1.0, 0.0), c(-2.0, 0.0)])
+            .unwrap()
+            .roots,
+        [c(2.0, 0.0)]
+    );
+    assert_eq!(Polynomial::new(vec![c(0.0, 0.0), c(5.0, 0.0)]), None);
+}
+
+impl FromStr for Polynomial {
+    type Err = String;
+
+    /// Parse coefficients like `1,0:0,0:0,0:-1,0`, each a complex number `RE,IM`, from that
+    /// of the highest power of `z` down to the constant term.
+    fn from_str(s: &str) -> Result<Polynomial, String> {
+        let coefficients = s
+            .split(':')
+            .map(parse_complex)
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| format!("error parsing polynomial coefficients '{}'", s))?;
+        Polynomial::new(coefficients)
+            .ok_or_else(|| format!("polynomial '{}' needs a degree of at least one", s))
+    }
+}
+
+#[test]
+fn test_parse_polynomial() {
+    let quadratic: Polynomial = "1,0:0,0:1,0".parse().unwrap();
+    assert_eq!(
+        quadratic.coefficients,
+        [c(1.0, 0.0), c(0.0, 0.0), c(1.0, 0.0)]
+    );
+    assert_eq!(quadratic.roots.len(), 2);
+    assert!("1,0:x".parse::<Polynomial>().is_err());
+    assert!("1,0".parse::<Polynomial>().is_err());
+    assert!("1:0,0".parse::<Polynomial>().is_err());
+}
+
+/// Render `view` of the basins of `polynomial` into `basins`, one per pixel of an image of
+/// the given `bounds`, iterating at most `limit` times, using `threads` worker threads.
+fn render(
+    basins: &mut [Option<Basin>],
+    bounds: (usize, usize),
+    view: &View,
+    polynomial: &Polynomial,
+    limit: usize,
+    threads: usize,
+) {
+    let tiles = tiles(bounds, (TILE_SIZE, TILE_SIZE));
+    render_tiles(basins, bounds, &tiles, threads, |buffer, tile| {
+        for row in 0..tile.height {
+            for column in 0..tile.width {
+                let pixel = (tile.left + column, tile.top + row);
+                let point = pixel_to_point(bounds, pixel, view.upper_left, view.lower_right);
+                buffer[row * tile.width + column] = polynomial.converge(view.rotate(point), limit);
+            }
+        }
+    });
+}
+
+/// Color `basins` for a polynomial with `roots` distinct roots, returning three bytes per
+/// pixel: each root takes a color spaced evenly along `palette`, darkened the more
+/// iterations it took to reach, and points that never converged take the palette's
+/// interior color.
+fn colorize(basins: &[Option<Basin>], roots: usize, palette: &Palette) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(basins.len() * 3);
+    for basin in basins {
+        let (color, shade) = match basin {
+            Some(basin) => (
+                palette.color(Some(basin.root as f64 / roots as f64)),
+                0.5f64.powf(basin.count as f64 / HALF_SHADE),
+            ),
+            None => (palette.color(None), 1.0),
+        };
+        for channel in [color.r, color.g, color.b] {
+            pixels.push((channel as f64 * shade).round() as u8);
+        }
+    }
+    pixels
+}
+
+#[test]
+fn test_colorize() {
+    let palette = Palette::builtin("rainbow").unwrap();
+    let basins = [
+        Some(Basin { root: 0, count: 0 }),
+        Some(Basin { root: 1, count: 10 }),
+        None,
+    ];
+    assert_eq!(
+        colorize(&basins, 2, &palette),
+        [255, 0, 0, 0, 128, 64, 0, 0, 0]
+    );
+}
+
+/// Draw the basins of `polynomial` with the settings in `args`, and write them to
+/// `args.filename` as an RGB PNG.
+pub fn render_basins(args: &Arguments, polynomial: &Polynomial) -> Result<(), io::Error> {
+    let bounds = args.sample_bounds();
+    let mut basins = vec![None; bounds.0 * bounds.1];
+    render(
+        &mut basins,
+        bounds,
+        &args.view,
+        polynomial,
+        args.fractal.limit,
+        args.threads,
+    );
+    let pixels = crate::supersample::downsample(
+        &colorize(&basins, polynomial.roots.len(), &args.coloring.palette),
+        bounds,
+        3,
+        args.supersample,
+    );
+    crate::write_image(&args.filename, &pixels, args.bounds, ColorType::RGB(8))
+}
@@ -0,0 +1,409 @@
+use crate::pngstream::PngStream;
+use crate::{Coloring, Sample};
+use image::ColorType;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// The first bytes of a raw sample file, identifying its format and version.
+pub const RAW_MAGIC: &[u8; 8] = b"MANDRAW1";
+
+/// How a single rendered image is written to disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageFormat {
+    /// An 8-bit PNG in the palette's color type.
+    Png,
+    /// A 16-bit grayscale PNG, for smooth shading without banding.
+    Png16,
+    /// A binary PGM for grayscale palettes, or PPM for color ones.
+    Pnm,
+    /// The samples themselves, uncolored, so they can be recolored later.
+    Raw,
+}
+
+impl ImageFormat {
+    /// The format named on the command line, such as `png16`.
+    pub fn from_name(name: &str) -> Option<ImageFormat> {
+        match name {
+            "png" => Some(ImageFormat::Png),
+            "png16" => Some(ImageFormat::Png16),
+            "pnm" | "ppm" | "pgm" => Some(ImageFormat::Pnm),
+            "raw" => Some(ImageFormat::Raw),
+            _ => None,
+        }
+    }
+
+    /// The name `from_name` takes for this format.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Png16 => "png16",
+            ImageFormat::Pnm => "pnm",
+            ImageFormat::Raw => "raw",
+        }
+    }
+
+    /// The format the extension of `filename` calls for, defaulting to PNG. A 16-bit PNG
+    /// has the same extension as any other, so it must be asked for by name.
+    pub fn from_filename(filename: &str) -> ImageFormat {
+        let extension = Path::new(filename)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("pnm" | "ppm" | "pgm") => ImageFormat::Pnm,
+            Some("raw") => ImageFormat::Raw,
+            _ => ImageFormat::Png,
+        }
+    }
+}
+
+#[test]
+fn test_image_format() {
+    assert_eq!(ImageFormat::from_filename("a.png"), ImageFormat::Png);
+    assert_eq!(ImageFormat::from_filename("a.PPM"), ImageFormat::Pnm);
+    assert_eq!(ImageFormat::from_filename("dir.raw/a"), ImageFormat::Png);
+    assert_eq!(ImageFormat::from_filename("a.raw"), ImageFormat::Raw);
+    assert_eq!(ImageFormat::from_name("pgm"), Some(ImageFormat::Pnm));
+    assert_eq!(ImageFormat::from_name("tiff"), None);
+    for format in [
+        ImageFormat::Png,
+        ImageFormat::Png16,
+        ImageFormat::Pnm,
+        ImageFormat::Raw,
+    ] {
+        assert_eq!(ImageFormat::from_name(format.name()), Some(format));
+    }
+}
+
+/// Shade `samples`, iterated with the given `limit`, along the gray palette's ramp with
+/// 16 bits per pixel: white for points that escape at once, fading to black. Only the
+/// shading and histogram of `coloring` matter.
+pub fn gray16(samples: &[Sample], limit: usize, coloring: &Coloring) -> Vec<u16> {
+    samples
+        .iter()
+        .map(|&sample| match coloring.position(sample, limit) {
+            None => 0,
+            Some(t) => (65535.0 * (1.0 - t)).round() as u16,
+        })
+        .collect()
+}
+
+#[test]
+fn test_gray16() {
+    let escaped = |count| Sample {
+        count,
+        fraction: 0.5,
+        distance: f32::INFINITY,
+        trap: f32::INFINITY,
+    };
+    let samples = [Sample::INTERIOR, escaped(0), escaped(51), escaped(1000)];
+    let coloring = Coloring::default();
+    assert_eq!(gray16(&samples, 255, &coloring), [0, 65535, 52428, 0]);
+    // Smooth shading lands between the 8-bit levels.
+    let smooth = Coloring {
+        shading: crate::Shading::Smooth,
+        ..coloring
+    };
+    assert_eq!(gray16(&samples, 255, &smooth)[2], 52300);
+}
+
+/// The samples behind an image, with what is needed to color them.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SampleGrid {
+    /// One sample per point, in row-major order.
+    pub samples: Vec<Sample>,
+    /// The width and height of the grid: `supersample` times those of the image.
+    pub bounds: (usize, usize),
+    /// Each pixel of the image averages `supersample` × `supersample` samples.
+    pub supersample: usize,
+    /// The iteration limit the samples were computed with.
+    pub limit: usize,
+}
+
+/// Writes an image in any `ImageFormat` a band of rows at a time, coloring each band of
+/// samples as it arrives, so the whole image never has to be in memory at once.
+pub struct ImageWriter<W: Write> {
+    format: ImageFormat,
+    coloring: Coloring,
+    output: Output<W>,
+}
+
+enum Output<W: Write> {
+    // The compressor's state is large; boxing it keeps the other variant small.
+    Png(Box<PngStream<W>>),
+    Plain(W),
+}
+
+impl<W: Write> ImageWriter<W> {
+    /// Start writing an image in `format`, colored with `coloring`, from samples in a grid
+    /// of the given `bounds` with the given `supersample` factor and iteration `limit`.
+    pub fn new(
+        mut output: W,
+        format: ImageFormat,
+        coloring: &Coloring,
+        bounds: (usize, usize),
+        supersample: usize,
+        limit: usize,
+    ) -> Result<ImageWriter<W>, io::Error> {
+        let image_bounds = (bounds.0 / supersample, bounds.1 / supersample);
+        let output = match format {
+            ImageFormat::Png => Output::Png(Box::new(PngStream::new(
+                output,
+                image_bounds,
+                coloring.palette.color_type(),
+            )?)),
+            ImageFormat::Png16 => Output::Png(Box::new(PngStream::new(
+                output,
+                image_bounds,
+                ColorType::Gray(16),
+            )?)),
+            ImageFormat::Pnm => {
+                write_pnm_header(&mut output, image_bounds, coloring.channels())?;
+                Output::Plain(output)
+            }
+            ImageFormat::Raw => {
+                write_raw_header(&mut output, bounds, supersample, limit)?;
+                Output::Plain(output)
+            }
+        };
+        Ok(ImageWriter {
+            format,
+            coloring: coloring.clone(),
+            output,
+        })
+    }
+
+    /// Write the next band of the image, whose rows must be a multiple of the
+    /// supersampling factor.
+    pub fn write_band(&mut self, band: &SampleGrid) -> Result<(), io::Error> {
+        match (&mut self.output, self.format) {
+            (Output::Png(stream), ImageFormat::Png16) => {
+                let gray = gray16(&band.samples, band.limit, &self.coloring);
+                let gray = crate::supersample::downsample(&gray, band.bounds, 1, band.supersample);
+                // PNG stores 16-bit samples big-endian.
+                let bytes: Vec<u8> = gray.iter().flat_map(|v| v.to_be_bytes()).collect();
+                stream.write_rows(&bytes)
+            }
+            (Output::Png(stream), _) => {
+                stream.write_rows(&crate::color_image(band, &self.coloring))
+            }
+            (Output::Plain(output), ImageFormat::Raw) => {
+                write_raw_rows(output, &band.samples, band.bounds.0)
+            }
+            (Output::Plain(output), _) => {
+                output.write_all(&crate::color_image(band, &self.coloring))
+            }
+        }
+    }
+
+    /// Write the end of the image, once every band has been written.
+    pub fn finish(self) -> Result<(), io::Error> {
+        match self.output {
+            Output::Png(stream) => stream.finish(),
+            Output::Plain(mut output) => output.flush(),
+        }
+    }
+}
+
+/// Write `grid` whole in `format`, colored with `coloring`, returning the file's bytes.
+#[cfg(test)]
+fn write_to_vec(format: ImageFormat, coloring: &Coloring, grid: &SampleGrid) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut writer = ImageWriter::new(
+        &mut data,
+        format,
+        coloring,
+        grid.bounds,
+        grid.supersample,
+        grid.limit,
+    )
+    .unwrap();
+    writer.write_band(grid).unwrap();
+    writer.finish().unwrap();
+    data
+}
+
+#[cfg(test)]
+fn test_grid() -> SampleGrid {
+    SampleGrid {
+        samples: vec![
+            Sample::INTERIOR,
+            Sample {
+                count: 3,
+                fraction: 0.5,
+                distance: f32::INFINITY,
+                trap: f32::INFINITY,
+            },
+        ],
+        bounds: (2, 1),
+        supersample: 1,
+        limit: 255,
+    }
+}
+
+/// Write the header of a binary PGM for grayscale images, or PPM for RGB ones, of the
+/// given `bounds`. Neither format has an alpha channel.
+fn write_pnm_header<W: Write>(
+    output: &mut W,
+    bounds: (usize, usize),
+    channels: usize,
+) -> Result<(), io::Error> {
+    let magic = match channels {
+        1 => "P5",
+        3 => "P6",
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "PPM cannot hold transparent pixels",
+            ))
+        }
+    };
+    write!(output, "{}\n{} {}\n255\n", magic, bounds.0, bounds.1)
+}
+
+#[test]
+fn test_pnm() {
+    let grid = test_grid();
+    let pgm = write_to_vec(ImageFormat::Pnm, &Coloring::default(), &grid);
+    assert_eq!(pgm, b"P5\n2 1\n255\n\x00\xfc");
+    let coloring = Coloring {
+        palette: crate::palette::Palette::builtin("fire").unwrap(),
+        ..Coloring::default()
+    };
+    let ppm = write_to_vec(ImageFormat::Pnm, &coloring, &grid);
+    assert_eq!(&ppm[..11], b"P6\n2 1\n255\n");
+    assert_eq!(ppm.len(), 11 + 6);
+    assert!(write_pnm_header(&mut Vec::new(), (1, 1), 4).is_err());
+}
+
+/// Write the header of a raw sample file: `RAW_MAGIC`, then the width, height,
+/// supersampling factor and limit as little-endian u32s. The samples follow in row-major
+/// order, each as its count (a u32, `u32::MAX` for points in the set) and fraction (an
+/// f32), both little-endian. Distance estimates are not kept.
+fn write_raw_header<W: Write>(
+    output: &mut W,
+    bounds: (usize, usize),
+    supersample: usize,
+    limit: usize,
+) -> Result<(), io::Error> {
+    let too_big = || io::Error::new(io::ErrorKind::InvalidInput, "image too large");
+    output.write_all(RAW_MAGIC)?;
+    for value in [bounds.0, bounds.1, supersample, limit] {
+        let value = u32::try_from(value).map_err(|_| too_big())?;
+        output.write_all(&value.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+/// Write rows of `width` samples in the raw format.
+fn write_raw_rows<W: Write>(
+    output: &mut W,
+    samples: &[Sample],
+    width: usize,
+) -> Result<(), io::Error> {
+    let mut row = Vec::with_capacity(width * 8);
+    for samples in samples.chunks(width) {
+        row.clear();
+        for sample in samples {
+            row.extend(sample.count.to_le_bytes());
+            row.extend(sample.fraction.to_le_bytes());
+        }
+        output.write_all(&row)?;
+    }
+    Ok(())
+}
+
+/// Reads a raw sample file a band of rows at a time.
+pub struct RawReader<R: Read> {
+    input: R,
+    /// The width and height of the whole grid.
+    pub bounds: (usize, usize),
+    pub supersample: usize,
+    pub limit: usize,
+    /// The number of rows not yet read.
+    rows_left: usize,
+}
+
+impl<R: Read> RawReader<R> {
+    /// Read the header of the raw sample file `input`.
+    pub fn new(mut input: R) -> Result<RawReader<R>, io::Error> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message);
+        let mut header = [0; 24];
+        input.read_exact(&mut header)?;
+        if &header[..8] != RAW_MAGIC {
+            return Err(invalid("not a raw sample file"));
+        }
+        let field =
+            |i: usize| u32::from_le_bytes(header[8 + i * 4..][..4].try_into().unwrap()) as usize;
+        let (bounds, supersample, limit) = ((field(0), field(1)), field(2), field(3));
+        if supersample == 0
+            || supersample > crate::supersample::MAX_FACTOR
+            || !bounds.0.is_multiple_of(supersample)
+            || !bounds.1.is_multiple_of(supersample)
+            || limit == 0
+        {
+            return Err(invalid("corrupt raw sample file header"));
+        }
+        Ok(RawReader {
+            input,
+            bounds,
+            supersample,
+            limit,
+            rows_left: bounds.1,
+        })
+    }
+
+    /// Read the next `rows` rows of samples, or as many as are left.
+    pub fn read_band(&mut self, rows: usize) -> Result<SampleGrid, io::Error> {
+        let rows = rows.min(self.rows_left);
+        let mut samples = Vec::with_capacity(self.bounds.0 * rows);
+        let mut row = vec![0; self.bounds.0 * 8];
+        for _ in 0..rows {
+            self.input.read_exact(&mut row)?;
+            samples.extend(row.chunks(8).map(|bytes| {
+                let count = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+                Sample {
+                    count,
+                    fraction: f32::from_le_bytes(bytes[4..].try_into().unwrap()),
+                    distance: if count == u32::MAX {
+                        0.0
+                    } else {
+                        f32::INFINITY
+                    },
+                    trap: f32::INFINITY,
+                }
+            }));
+        }
+        self.rows_left -= rows;
+        Ok(SampleGrid {
+            samples,
+            bounds: (self.bounds.0, rows),
+            supersample: self.supersample,
+            limit: self.limit,
+        })
+    }
+}
+
+#[test]
+fn test_raw() {
+    let grid = test_grid();
+    let mut raw = write_to_vec(ImageFormat::Raw, &Coloring::default(), &grid);
+    assert_eq!(&raw[..8], RAW_MAGIC);
+    assert_eq!(
+        raw[8..24],
+        [2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 255, 0, 0, 0]
+    );
+    assert_eq!(raw[24..28], [255; 4]);
+    assert_eq!(raw[32..36], [3, 0, 0, 0]);
+    assert_eq!(raw[36..40], 0.5f32.to_le_bytes());
+    assert_eq!(raw.len(), 40);
+    let mut reader = RawReader::new(&raw[..]).unwrap();
+    assert_eq!(reader.read_band(10).unwrap(), grid);
+    assert_eq!(reader.read_band(10).unwrap().samples, []);
+
+    // Truncated files and other formats are refused.
+    assert!(RawReader::new(&raw[..39]).unwrap().read_band(1).is_err());
+    assert!(RawReader::new(&b"\x89PNG\r\n\x1a\n"[..]).is_err());
+    raw[16] = 0;
+    assert!(RawReader::new(&raw[..]).is_err());
+}
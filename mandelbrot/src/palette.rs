@@ -0,0 +1,313 @@
+use image::ColorType;
+use std::fmt;
+use std::fs::read_to_string;
+use std::io;
+
+/// An RGBA color with 8 bits per channel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Rgba {
+        Rgba { r, g, b, a: 255 }
+    }
+
+    fn lerp(self, other: Rgba, t: f64) -> Rgba {
+        let mix = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+        Rgba {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+}
+
+impl fmt::Display for Rgba {
+    /// Write the color as `parse_color` reads it: `#RRGGBB`, or `#RRGGBBAA` unless opaque.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.r, self.g, self.b)?;
+        if self.a != 255 {
+            write!(f, "{:02x}", self.a)?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a color written as `RRGGBB` or `RRGGBBAA` hex digits, with an optional leading `#`.
+fn parse_color(s: &str) -> Option<Rgba> {
+    let s = s.strip_prefix('#').unwrap_or(s);
+    if !(s.len() == 6 || s.len() == 8) || !s.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).ok();
+    Some(Rgba {
+        r: channel(0)?,
+        g: channel(2)?,
+        b: channel(4)?,
+        a: if s.len() == 8 { channel(6)? } else { 255 },
+    })
+}
+
+#[test]
+fn test_parse_color() {
+    assert_eq!(parse_color("#ff8000"), Some(Rgba::rgb(255, 128, 0)));
+    assert_eq!(
+        parse_color("00000080"),
+        Some(Rgba {
+            r: 0,
+            g: 0,
+            b: 0,
+            a: 128
+        })
+    );
+    assert_eq!(parse_color("#fff"), None);
+    assert_eq!(parse_color("#gg0000"), None);
+}
+
+/// A color gradient: a list of stops at positions in `0.0..=1.0`, plus the color used
+/// for points that never escape.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    stops: Vec<(f64, Rgba)>,
+    interior: Rgba,
+}
+
+const BUILTIN_NAMES: &[&str] = &["gray", "fire", "ocean", "ultra", "rainbow"];
+
+impl Palette {
+    fn new(mut stops: Vec<(f64, Rgba)>, interior: Rgba) -> Palette {
+        assert!(!stops.is_empty());
+        stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Palette { stops, interior }
+    }
+
+    /// The palette matching the tool's original output: white for points that escape
+    /// at once, fading to black for points that never escape.
+    pub fn gray() -> Palette {
+        Palette::new(
+            vec![(0.0, Rgba::rgb(255, 255, 255)), (1.0, Rgba::rgb(0, 0, 0))],
+            Rgba::rgb(0, 0, 0),
+        )
+    }
+
+    /// Look up one of the built-in palettes by name.
+    pub fn builtin(name: &str) -> Option<Palette> {
+        let black = Rgba::rgb(0, 0, 0);
+        let palette = match name {
+            "gray" => Palette::gray(),
+            "fire" => Palette::new(
+                vec![
+                    (0.0, Rgba::rgb(0, 0, 0)),
+                    (0.25, Rgba::rgb(128, 0, 0)),
+                    (0.5, Rgba::rgb(255, 64, 0)),
+                    (0.75, Rgba::rgb(255, 200, 0)),
+                    (1.0, Rgba::rgb(255, 255, 224)),
+                ],
+                black,
+            ),
+            "ocean" => Palette::new(
+                vec![
+                    (0.0, Rgba::rgb(0, 8, 32)),
+                    (0.4, Rgba::rgb(0, 64, 128)),
+                    (0.7, Rgba::rgb(0, 160, 192)),
+                    (1.0, Rgba::rgb(224, 255, 255)),
+                ],
+                black,
+            ),
+            "ultra" => Palette::new(
+                vec![
+                    (0.0, Rgba::rgb(0, 7, 100)),
+                    (0.16, Rgba::rgb(32, 107, 203)),
+                    (0.42, Rgba::rgb(237, 255, 255)),
+                    (0.6425, Rgba::rgb(255, 170, 0)),
+                    (0.8575, Rgba::rgb(0, 2, 0)),
+                    (1.0, Rgba::rgb(0, 7, 100)),
+                ],
+                black,
+            ),
+            "rainbow" => Palette::new(
+                vec![
+                    (0.0, Rgba::rgb(255, 0, 0)),
+                    (0.2, Rgba::rgb(255, 255, 0)),
+                    (0.4, Rgba::rgb(0, 255, 0)),
+                    (0.6, Rgba::rgb(0, 255, 255)),
+                    (0.8, Rgba::rgb(0, 0, 255)),
+                    (1.0, Rgba::rgb(255, 0, 255)),
+                ],
+                black,
+            ),
+            _ => return None,
+        };
+        Some(palette)
+    }
+
+    /// The names accepted by `Palette::builtin`.
+    pub fn builtin_names() -> &'static [&'static str] {
+        BUILTIN_NAMES
+    }
+
+    /// Parse a gradient description. Each non-blank line is either a stop,
+    /// `POSITION COLOR`, or `inside COLOR` giving the color for points in the set.
+    /// Colors are hex `RRGGBB` or `RRGGBBAA`; lines starting with `//` are comments.
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        let mut stops = Vec::new();
+        let mut interior = Rgba::rgb(0, 0, 0);
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() != 2 {
+                return Err(format!("line {}: expected POSITION COLOR", number + 1));
+            }
+            let color = parse_color(fields[1])
+                .ok_or_else(|| format!("line {}: bad color '{}'", number + 1, fields[1]))?;
+            if fields[0] == "inside" {
+                interior = color;
+                continue;
+            }
+            match fields[0].parse::<f64>() {
+                Ok(position) if (0.0..=1.0).contains(&position) => stops.push((position, color)),
+                _ => {
+                    return Err(format!(
+                        "line {}: position '{}' is not a number between 0 and 1",
+                        number + 1,
+                        fields[0]
+                    ))
+                }
+            }
+        }
+        if stops.is_empty() {
+            return Err("gradient has no color stops".to_string());
+        }
+        Ok(Palette::new(stops, interior))
+    }
+
+    /// Load a gradient file in the format accepted by `Palette::parse`.
+    pub fn load(filename: &str) -> Result<Palette, io::Error> {
+        let text = read_to_string(filename)?;
+        Palette::parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Return the color at position `t` along the gradient, or the interior color
+    /// if `t` is `None`.
+    pub fn color(&self, t: Option<f64>) -> Rgba {
+        let t = match t {
+            None => return self.interior,
+            Some(t) => t,
+        };
+        let first = self.stops[0];
+        if t <= first.0 {
+            return first.1;
+        }
+        for pair in self.stops.windows(2) {
+            let ((p0, c0), (p1, c1)) = (pair[0], pair[1]);
+            if t <= p1 {
+                return c0.lerp(c1, (t - p0) / (p1 - p0));
+            }
+        }
+        self.stops[self.stops.len() - 1].1
+    }
+
+    /// The narrowest PNG color type that can represent every color in the palette.
+    pub fn color_type(&self) -> ColorType {
+        let colors = || self.stops.iter().map(|s| &s.1).chain(Some(&self.interior));
+        if colors().any(|c| c.a != 255) {
+            ColorType::RGBA(8)
+        } else if colors().all(|c| c.r == c.g && c.g == c.b) {
+            ColorType::Gray(8)
+        } else {
+            ColorType::RGB(8)
+        }
+    }
+
+    /// The number of bytes each pixel occupies in the output of `paint`.
+    pub fn channels(&self) -> usize {
+        match self.color_type() {
+            ColorType::Gray(_) => 1,
+            ColorType::RGB(_) => 3,
+            _ => 4,
+        }
+    }
+
+    /// Store the color for `t` into `pixel`, which must be `channels()` bytes long.
+    pub fn paint(&self, pixel: &mut [u8], t: Option<f64>) {
+        let c = self.color(t);
+        match pixel.len() {
+            1 => pixel[0] = c.r,
+            3 => pixel.copy_from_slice(&[c.r, c.g, c.b]),
+            4 => pixel.copy_from_slice(&[c.r, c.g, c.b, c.a]),
+            n => panic!("cannot paint a {}-byte pixel", n),
+        }
+    }
+}
+
+impl fmt::Display for Palette {
+    /// Write the palette as a gradient description that `Palette::parse` reads back.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (position, color) in &self.stops {
+            writeln!(f, "{} {}", position, color)?;
+        }
+        writeln!(f, "inside {}", self.interior)
+    }
+}
+
+#[test]
+fn test_gray_matches_original_shading() {
+    let gray = Palette::gray();
+    assert_eq!(gray.color_type(), ColorType::Gray(8));
+    for count in 0..255 {
+        assert_eq!(gray.color(Some(count as f64 / 255.0)).r, 255 - count as u8);
+    }
+    assert_eq!(gray.color(None), Rgba::rgb(0, 0, 0));
+}
+
+#[test]
+fn test_builtin_palettes() {
+    for name in Palette::builtin_names() {
+        assert!(Palette::builtin(name).is_some(), "missing palette {}", name);
+    }
+    assert_eq!(Palette::builtin("nonesuch"), None);
+    assert_eq!(
+        Palette::builtin("fire").unwrap().color_type(),
+        ColorType::RGB(8)
+    );
+}
+
+#[test]
+fn test_parse_gradient() {
+    let palette = Palette::parse(
+        "// blue to red\n\
+         1.0 #ff0000\n\
+         0.0 #0000ff\n\
+         \n\
+         inside 00000000\n",
+    )
+    .unwrap();
+    assert_eq!(palette.color(Some(0.0)), Rgba::rgb(0, 0, 255));
+    assert_eq!(palette.color(Some(0.5)), Rgba::rgb(128, 0, 128));
+    assert_eq!(palette.color(Some(2.0)), Rgba::rgb(255, 0, 0));
+    assert_eq!(palette.color(None).a, 0);
+    assert_eq!(palette.color_type(), ColorType::RGBA(8));
+
+    assert!(Palette::parse("").is_err());
+    assert!(Palette::parse("0.5").is_err());
+    assert!(Palette::parse("1.5 #ffffff").is_err());
+    assert!(Palette::parse("0.5 white").is_err());
+
+    // Writing a palette out and parsing it again gives the same palette.
+    for palette in Palette::builtin_names()
+        .iter()
+        .map(|name| Palette::builtin(name).unwrap())
+        .chain(Some(palette))
+    {
+        assert_eq!(Palette::parse(&palette.to_string()), Ok(palette));
+    }
+}
@@ -0,0 +1,197 @@
+use crate::fixed::{Fixed, FixedComplex};
+use crate::formula::Formula;
+use crate::tiles::{render_tiles, tiles, TILE_SIZE};
+use crate::view::View;
+use crate::{Escape, Fractal, Mode, Sample, Tracking, EXTRA_ITERATIONS};
+use num::Complex;
+
+/// Below this pixel spacing the per-pixel deltas would underflow f64, so perturbation
+/// can no longer be used.
+const MIN_SPACING: f64 = 1e-290;
+
+/// Return true if `fractal` can be rendered by perturbation at this pixel spacing.
+pub fn supports(fractal: &Fractal, spacing: f64) -> bool {
+    fractal.formula == Formula::Mandelbrot && spacing > MIN_SPACING
+}
+
+/// Iterate the orbit starting at `z` in arbitrary precision, and return each of its
+/// values rounded to f64, up to and including the one that escapes.
+fn reference_orbit(
+    mut z: FixedComplex,
+    c: &FixedComplex,
+    limit: usize,
+    bailout: f64,
+) -> Vec<Complex<f64>> {
+    let mut orbit = Vec::with_capacity(limit + 1);
+    for _ in 0..=limit {
+        orbit.push(z.to_complex());
+        if z.norm_sqr() > bailout * bailout {
+            break;
+        }
+        z = &z.square() + c;
+    }
+    orbit
+}
+
+/// Like `escape_time`, but following the pixel's orbit as an offset `dz` from the
+/// `reference` orbit, where the pixel's orbit starts at `reference[0] + dz` and its
+/// constant is the reference's plus `dc`. With `Z` the reference and `z = Z + dz`,
+/// `z² + c = Z² + C + (2Z + dz)dz + dc`, so `dz` can be iterated on its own in f64.
+///
+/// When `|z|` drops below `|dz|`, the offset is no longer small compared to the orbit,
+/// and continuing would lose precision (a "glitch"). At that point, or when the
+/// reference orbit runs out, we rebase: restart from the beginning of the reference
+/// orbit with `dz` set so that `reference[0] + dz` is the current `z`.
+///
+/// Whatever `tracking` follows needs no such care: it is followed directly, using the
+/// full `z`.
+fn escape_time_perturbed(
+    reference: &[Complex<f64>],
+    c: Complex<f64>,
+    mut dz: Complex<f64>,
+    dc: Complex<f64>,
+    mut tracking: Tracking,
+    limit: usize,
+    bailout: f64,
+) -> Option<Escape> {
+    let mut m = 0;
+    for i in 0..limit {
+        let mut z = reference[m] + dz;
+        if i > 0 {
+            tracking.visit(z);
+        }
+        if z.norm_sqr() > bailout * bailout {
+            for _ in 0..EXTRA_ITERATIONS {
+                tracking.step(&Formula::Mandelbrot, z);
+                z = z * z + c;
+            }
+            return Some(tracking.escape(i, z));
+        }
+        tracking.step(&Formula::Mandelbrot, z);
+        if z.norm_sqr() < dz.norm_sqr() || m + 1 == reference.len() {
+            dz = z - reference[0];
+            m = 0;
+        }
+        dz = (reference[m] * 2.0 + dz) * dz + dc;
+        m += 1;
+    }
+    None
+}
+
+/// Render `view` of `fractal` like `deep::render`, but computing only the orbit at
+/// the center of the view in arbitrary precision, and every pixel's orbit as an f64
+/// offset from it.
+pub fn render(
+    samples: &mut [Sample],
+    bounds: (usize, usize),
+    view: &View,
+    bits: u32,
+    fractal: &Fractal,
+    threads: usize,
+) {
+    let (limit, bailout) = (fractal.limit, fractal.bailout);
+    let upper_left = view.upper_left_exact.with_bits(bits);
+    let lower_right = view.lower_right_exact.with_bits(bits);
+    let width = (&lower_right.re - &upper_left.re).to_f64();
+    let height = (&upper_left.im - &lower_right.im).to_f64();
+    let center = view.center_exact(bits);
+    let spacing = crate::deep::pixel_spacing(bounds, view.upper_left, view.lower_right);
+    let tracking = fractal.tracking();
+
+    // In Mandelbrot mode the pixel perturbs `c`; in Julia mode it perturbs the start.
+    let (reference, c) = match fractal.mode {
+        Mode::Mandelbrot => {
+            let zero = FixedComplex {
+                re: Fixed::zero(bits),
+                im: Fixed::zero(bits),
+            };
+            (
+                reference_orbit(zero, &center, limit, bailout),
+                center.to_complex(),
+            )
+        }
+        Mode::Julia(c) => (
+            reference_orbit(center, &FixedComplex::from_complex(c, bits), limit, bailout),
+            c,
+        ),
+    };
+
+    let tiles = tiles(bounds, (TILE_SIZE, TILE_SIZE));
+    render_tiles(samples, bounds, &tiles, threads, |buffer, tile| {
+        for row in 0..tile.height {
+            for column in 0..tile.width {
+                let offset = view.rotate_offset(Complex {
+                    re: width * ((tile.left + column) as f64 / bounds.0 as f64 - 0.5),
+                    im: -height * ((tile.top + row) as f64 / bounds.1 as f64 - 0.5),
+                });
+                let zero = Complex { re: 0.0, im: 0.0 };
+                let escape = match fractal.mode {
+                    Mode::Mandelbrot => escape_time_perturbed(
+                        &reference,
+                        c + offset,
+                        zero,
+                        offset,
+                        tracking,
+                        limit,
+                        bailout,
+                    ),
+                    Mode::Julia(c) => {
+                        escape_time_perturbed(&reference, c, offset, zero, tracking, limit, bailout)
+                    }
+                };
+                buffer[row * tile.width + column] = fractal.sample(escape, spacing);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+fn count_differences(bounds: (usize, usize), view: &View, bits: u32, mode: Mode) -> usize {
+    // Measuring the orbits against a trap checks that both paths visit the same points.
+    let fractal = Fractal {
+        mode,
+        trap: "line:0,0.5,30".parse().ok(),
+        ..Fractal::default()
+    };
+    let mut expected = vec![Sample::default(); bounds.0 * bounds.1];
+    crate::deep::render(&mut expected, bounds, view, bits, &fractal, 4);
+    let mut actual = vec![Sample::default(); bounds.0 * bounds.1];
+    render(&mut actual, bounds, view, bits, &fractal, 4);
+    expected
+        .iter()
+        .zip(&actual)
+        .filter(|(e, a)| e.count != a.count || (e.trap - a.trap).abs() > 1e-4)
+        .count()
+}
+
+#[test]
+fn test_perturbation_matches_fixed() {
+    let bounds = (32, 24);
+    let pixels = bounds.0 * bounds.1;
+    // A shallow view, where the offsets are large and rebasing happens often.
+    let mut view = View::corners("-1.20,0.35", "-1.0,0.20").unwrap();
+    let differences = count_differences(bounds, &view, 64, Mode::Mandelbrot);
+    assert!(differences <= pixels / 50, "{} differences", differences);
+    // Both paths should turn the view the same way.
+    view.rotation = 2.0;
+    let differences = count_differences(bounds, &view, 64, Mode::Mandelbrot);
+    assert!(differences <= pixels / 50, "{} differences", differences);
+    // A view far beyond the reach of plain f64.
+    let view = View::corners(
+        "-1.99999999999999999990,0.0000000000000000000075",
+        "-1.99999999999999999980,-0.0000000000000000000075",
+    )
+    .unwrap();
+    let differences = count_differences(bounds, &view, 100, Mode::Mandelbrot);
+    assert!(differences <= pixels / 50, "{} differences", differences);
+    let differences = count_differences(
+        bounds,
+        &View::corners("-1.6,0.9", "1.6,-0.9").unwrap(),
+        64,
+        Mode::Julia(Complex {
+            re: -0.8,
+            im: 0.156,
+        }),
+    );
+    assert!(differences <= pixels / 50, "{} differences", differences);
+}
@@ -0,0 +1,194 @@
+use deflate::write::ZlibEncoder;
+use deflate::Compression;
+use image::ColorType;
+use std::io::{self, Write};
+
+/// The most compressed data `PngStream` holds before writing it out as an IDAT chunk.
+const IDAT_SIZE: usize = 1 << 16;
+
+/// The CRC-32 lookup table PNG chunks are checksummed with.
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+}
+
+/// Write one PNG chunk: its length, type, data and checksum.
+pub fn write_chunk<W: Write>(output: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<(), io::Error> {
+    let mut crc = !0u32;
+    for &byte in kind.iter().chain(data) {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    output.write_all(&(data.len() as u32).to_be_bytes())?;
+    output.write_all(kind)?;
+    output.write_all(data)?;
+    output.write_all(&(!crc).to_be_bytes())
+}
+
+/// Write the PNG signature and the header chunk for an image of the given `bounds` and
+/// `color_type`.
+pub fn write_header<W: Write>(
+    output: &mut W,
+    bounds: (usize, usize),
+    color_type: ColorType,
+) -> Result<(), io::Error> {
+    let (depth, color) = match color_type {
+        ColorType::Gray(depth @ (8 | 16)) => (depth, 0),
+        ColorType::RGB(8) => (8, 2),
+        ColorType::RGBA(8) => (8, 6),
+        other => panic!("unsupported color type {:?}", other),
+    };
+    let too_big = || io::Error::new(io::ErrorKind::InvalidInput, "image too large for PNG");
+    let width = u32::try_from(bounds.0).map_err(|_| too_big())?;
+    let height = u32::try_from(bounds.1).map_err(|_| too_big())?;
+    output.write_all(b"\x89PNG\r\n\x1a\n")?;
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    header.extend([depth, color, 0, 0, 0]);
+    write_chunk(output, b"IHDR", &header)
+}
+
+/// Collects compressed image data, writing it out in IDAT chunks of `IDAT_SIZE` bytes.
+struct IdatWriter<W: Write> {
+    output: W,
+    buffer: Vec<u8>,
+}
+
+impl<W: Write> IdatWriter<W> {
+    fn write_chunk(&mut self) -> Result<(), io::Error> {
+        if !self.buffer.is_empty() {
+            write_chunk(&mut self.output, b"IDAT", &self.buffer)?;
+            self.buffer.clear();
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for IdatWriter<W> {
+    fn write(&mut self, data: &[u8]) -> Result<usize, io::Error> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= IDAT_SIZE {
+            self.write_chunk()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+/// Writes a PNG a few rows at a time, compressing them as they arrive, so the whole image
+/// never has to be in memory at once.
+pub struct PngStream<W: Write> {
+    encoder: ZlibEncoder<IdatWriter<W>>,
+    /// The number of bytes in each row of pixels.
+    row_bytes: usize,
+    /// The number of rows still to come.
+    rows_left: usize,
+}
+
+impl<W: Write> PngStream<W> {
+    /// Start a PNG of the given `bounds` and `color_type`.
+    pub fn new(
+        mut output: W,
+        bounds: (usize, usize),
+        color_type: ColorType,
+    ) -> Result<PngStream<W>, io::Error> {
+        write_header(&mut output, bounds, color_type)?;
+        let bytes_per_pixel = match color_type {
+            ColorType::Gray(8) => 1,
+            ColorType::Gray(16) => 2,
+            ColorType::RGB(8) => 3,
+            _ => 4,
+        };
+        let idat = IdatWriter {
+            output,
+            buffer: Vec::with_capacity(IDAT_SIZE),
+        };
+        Ok(PngStream {
+            encoder: ZlibEncoder::new(idat, Compression::Default),
+            row_bytes: bounds.0 * bytes_per_pixel,
+            rows_left: bounds.1,
+        })
+    }
+
+    /// Add the next rows of the image, laid out as for `write_image`, with 16-bit values
+    /// big-endian.
+    pub fn write_rows(&mut self, pixels: &[u8]) -> Result<(), io::Error> {
+        assert!(pixels.len().is_multiple_of(self.row_bytes));
+        for row in pixels.chunks(self.row_bytes) {
+            assert!(self.rows_left > 0, "more rows than the image holds");
+            // Every row starts with its filter type; we use none.
+            self.encoder.write_all(&[0])?;
+            self.encoder.write_all(row)?;
+            self.rows_left -= 1;
+        }
+        Ok(())
+    }
+
+    /// Write the end of the image, once every row has been written.
+    pub fn finish(self) -> Result<(), io::Error> {
+        assert_eq!(self.rows_left, 0, "image finished early");
+        let mut idat = self.encoder.finish()?;
+        idat.write_chunk()?;
+        write_chunk(&mut idat.output, b"IEND", &[])?;
+        idat.output.flush()
+    }
+}
+
+#[test]
+fn test_png_stream() {
+    use image::ImageDecoder;
+
+    // Noise compresses poorly, so it takes several IDAT chunks.
+    let bounds = (300, 200);
+    let mut state = 1u32;
+    let pixels: Vec<u8> = (0..bounds.0 * bounds.1 * 3)
+        .map(|_| {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        })
+        .collect();
+    let mut data = Vec::new();
+    let mut stream = PngStream::new(&mut data, bounds, ColorType::RGB(8)).unwrap();
+    for band in pixels.chunks(bounds.0 * 3 * 7) {
+        stream.write_rows(band).unwrap();
+    }
+    stream.finish().unwrap();
+
+    let idats = data.windows(4).filter(|kind| kind == b"IDAT").count();
+    assert!(idats > 1);
+    let mut decoder = image::png::PNGDecoder::new(&data[..]);
+    assert_eq!(decoder.dimensions().unwrap(), (300, 200));
+    match decoder.read_image().unwrap() {
+        image::DecodingResult::U8(decoded) => assert!(decoded == pixels),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn test_header_too_large() {
+    // A width that doesn't fit the header's 32 bits is an error, not a corrupt file.
+    let mut data = Vec::new();
+    let error = write_header(&mut data, ((1 << 32) + 1, 1), ColorType::Gray(8)).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    assert!(data.is_empty());
+}
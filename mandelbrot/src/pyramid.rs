@@ -0,0 +1,155 @@
+use crate::tiles::Tile;
+use crate::view::View;
+use crate::Arguments;
+use num::Complex;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The width and height of each tile of a pyramid, as web map viewers expect.
+pub const TILE_SIZE: usize = 256;
+
+/// The deepest zoom level `--pyramid` accepts. Each level has four times as many tiles as
+/// the one before, so this is already far more than could ever be rendered.
+pub const MAX_LEVEL: usize = 30;
+
+/// The width and height in pixels of the whole image at zoom `level`.
+pub fn level_size(level: usize) -> usize {
+    TILE_SIZE << level
+}
+
+/// The file holding the tile in column `x` and row `y` of zoom `level`, in the `z/x/y`
+/// layout web map viewers use.
+pub fn tile_filename(directory: &str, level: usize, x: usize, y: usize) -> String {
+    format!("{}/{}/{}/{}.png", directory, level, x, y)
+}
+
+#[test]
+fn test_tile_filename() {
+    assert_eq!(tile_filename("out", 3, 5, 7), "out/3/5/7.png");
+}
+
+/// The part of `view` shown by the tile in column `x` and row `y` of zoom `level`. Level 0
+/// is a single tile showing the whole view, and each level after it splits every tile of
+/// the one before into four.
+pub fn tile_view(view: &View, level: usize, x: usize, y: usize) -> View {
+    let size = level_size(level);
+    let tile = Tile {
+        left: x * TILE_SIZE,
+        top: y * TILE_SIZE,
+        width: TILE_SIZE,
+        height: TILE_SIZE,
+    };
+    view.tile((size, size), tile)
+}
+
+#[test]
+fn test_tile_view() {
+    let view = View::corners("-2,1.5", "1,-1.5").unwrap();
+    let whole = tile_view(&view, 0, 0, 0);
+    assert_eq!(
+        (whole.upper_left, whole.lower_right),
+        (view.upper_left, view.lower_right)
+    );
+    let tile = tile_view(&view, 1, 1, 0);
+    assert_eq!(tile.upper_left, Complex { re: -0.5, im: 1.5 });
+    assert_eq!(tile.lower_right, Complex { re: 1.0, im: 0.0 });
+    let tile = tile_view(&view, 2, 0, 3);
+    assert_eq!(
+        tile.upper_left,
+        Complex {
+            re: -2.0,
+            im: -0.75
+        }
+    );
+    assert_eq!(
+        tile.lower_right,
+        Complex {
+            re: -1.25,
+            im: -1.5
+        }
+    );
+}
+
+/// A JSON description of a pyramid of `levels` zoom levels beyond the first over `view`,
+/// for setting up a viewer.
+pub fn manifest(view: &View, levels: usize) -> String {
+    let point = |z: Complex<f64>| format!("[{}, {}]", z.re, z.im);
+    format!(
+        concat!(
+            "{{\n",
+            "  \"tileSize\": {},\n",
+            "  \"minZoom\": 0,\n",
+            "  \"maxZoom\": {},\n",
+            "  \"tiles\": \"{{z}}/{{x}}/{{y}}.png\",\n",
+            "  \"upperLeft\": {},\n",
+            "  \"lowerRight\": {},\n",
+            "  \"rotation\": {}\n",
+            "}}\n"
+        ),
+        TILE_SIZE,
+        levels,
+        point(view.upper_left),
+        point(view.lower_right),
+        view.rotation.to_degrees()
+    )
+}
+
+#[test]
+fn test_manifest() {
+    let view = View::corners("-2,1.5", "1,-1.5").unwrap();
+    let text = manifest(&view, 4);
+    assert!(text.contains("\"maxZoom\": 4,"));
+    assert!(text.contains("\"tiles\": \"{z}/{x}/{y}.png\","));
+    assert!(text.contains("\"upperLeft\": [-2, 1.5],"));
+    assert!(text.contains("\"rotation\": 0\n"));
+}
+
+/// Render every tile of a pyramid of `levels` zoom levels beyond the first over
+/// `args.view` into the directory `args.filename`, along with a `pyramid.json` manifest.
+/// Tiles that already exist are skipped, so an interrupted export can be carried on, or a
+/// finished one deepened, by running it again.
+///
+/// Tiles are written under a temporary name and renamed once complete, so an interrupted
+/// run never leaves a partial tile behind to be mistaken for a finished one.
+pub fn render_pyramid(args: &Arguments, levels: usize) -> Result<(), io::Error> {
+    let directory = &args.filename;
+    fs::create_dir_all(directory)?;
+    fs::write(
+        Path::new(directory).join("pyramid.json"),
+        manifest(&args.view, levels),
+    )?;
+    let bounds = (TILE_SIZE * args.supersample, TILE_SIZE * args.supersample);
+    for level in 0..=levels {
+        let count = 1 << level;
+        let mut rendered = 0;
+        for x in 0..count {
+            fs::create_dir_all(format!("{}/{}/{}", directory, level, x))?;
+            for y in 0..count {
+                let filename = tile_filename(directory, level, x, y);
+                if Path::new(&filename).exists() {
+                    continue;
+                }
+                let view = tile_view(&args.view, level, x, y);
+                let grid = crate::render_grid(args, &view, bounds);
+                let partial = format!("{}.partial", filename);
+                crate::write_image(
+                    &partial,
+                    &crate::color_image(&grid, &args.coloring),
+                    (TILE_SIZE, TILE_SIZE),
+                    args.coloring.palette.color_type(),
+                )?;
+                fs::rename(&partial, &filename)?;
+                rendered += 1;
+            }
+        }
+        eprintln!(
+            "level {} of {}: rendered {} of {} tiles",
+            level,
+            levels,
+            rendered,
+            count * count
+        );
+    }
+    Ok(())
+}
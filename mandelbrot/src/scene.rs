@@ -0,0 +1,352 @@
+use crate::fixed::{Fixed, FixedComplex};
+use crate::output::ImageFormat;
+use crate::palette::Palette;
+use crate::{Arguments, Mode, Shading};
+use num::Complex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// A description of an image to render, read from a TOML or JSON file in place of the
+/// command line's arguments. Each field stands for the command-line option or argument
+/// of the same name, and any left out take their usual defaults.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Scene {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// The image's size in pixels, such as `1000x750`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upper_left: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lower_right: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub center: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zoom: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rotate: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub formula: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub julia: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_iter: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bailout: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub palette: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gradient: Option<String>,
+    /// The color stops of a gradient written out in the scene itself, in the format of a
+    /// gradient file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stops: Option<String>,
+    /// `banded`, `smooth`, `distance` or `line-art`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shading: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trap: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub histogram: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supersample: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+}
+
+/// Whether `filename` names a JSON scene file rather than a TOML one.
+fn is_json(filename: &str) -> bool {
+    Path::new(filename)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("json"))
+}
+
+impl Scene {
+    /// Parse a scene from `text`, written in JSON if `json` is set, or TOML otherwise.
+    fn parse(text: &str, json: bool) -> Result<Scene, String> {
+        let scene: Scene = if json {
+            serde_json::from_str(text).map_err(|e| e.to_string())?
+        } else {
+            toml::from_str(text).map_err(|e| e.to_string())?
+        };
+        let palettes = [&scene.palette, &scene.gradient, &scene.stops];
+        if palettes.iter().filter(|p| p.is_some()).count() > 1 {
+            return Err("a scene takes only one of palette, gradient and stops".to_string());
+        }
+        if let Some(stops) = &scene.stops {
+            Palette::parse(stops).map_err(|e| format!("stops: {}", e))?;
+        }
+        if let Some(shading) = &scene.shading {
+            if !["banded", "smooth", "distance", "line-art"].contains(&shading.as_str()) {
+                return Err(format!("unknown shading '{}'", shading));
+            }
+            if scene.trap.is_some() {
+                return Err("a scene takes only one of shading and trap".to_string());
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Load the scene file `filename`: JSON if its extension is `.json`, or TOML otherwise.
+    pub fn load(filename: &str) -> Result<Scene, io::Error> {
+        let text = fs::read_to_string(filename)?;
+        Scene::parse(&text, is_json(filename))
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Write the scene to `filename`, in JSON if its extension is `.json`, or TOML
+    /// otherwise.
+    pub fn save(&self, filename: &str) -> Result<(), io::Error> {
+        let text = if is_json(filename) {
+            serde_json::to_string_pretty(self)? + "\n"
+        } else {
+            toml::to_string_pretty(self)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+        };
+        fs::write(filename, text)
+    }
+
+    /// The command-line options this scene stands for.
+    pub fn options(&self) -> Vec<String> {
+        let mut options = Vec::new();
+        let mut option = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                options.push(name.to_string());
+                options.push(value);
+            }
+        };
+        option("--center", self.center.clone());
+        option("--zoom", self.zoom.map(|z| z.to_string()));
+        option("--radius", self.radius.map(|r| r.to_string()));
+        option("--rotate", self.rotate.map(|d| d.to_string()));
+        option("--formula", self.formula.clone());
+        option("--julia", self.julia.clone());
+        option("--max-iter", self.max_iter.map(|n| n.to_string()));
+        option("--bailout", self.bailout.map(|r| r.to_string()));
+        option("--palette", self.palette.clone());
+        option("--gradient", self.gradient.clone());
+        option("--trap", self.trap.clone());
+        option("--supersample", self.supersample.map(|n| n.to_string()));
+        option("--format", self.format.clone());
+        // Banded shading is the default, with no option of its own.
+        if let Some(shading) = self.shading.as_deref().filter(|&s| s != "banded") {
+            options.push(format!("--{}", shading));
+        }
+        if self.histogram == Some(true) {
+            options.push("--histogram".to_string());
+        }
+        options
+    }
+
+    /// The palette given by the scene's `stops`, which has no command-line option.
+    pub fn stops(&self) -> Option<Palette> {
+        // `parse` has already checked that the stops are valid.
+        self.stops.as_deref().and_then(|s| Palette::parse(s).ok())
+    }
+
+    /// The positional arguments this scene stands for: its output file, or `filename` in
+    /// its place if given, then its size and corners, leaving out any it doesn't have.
+    pub fn arguments(&self, filename: Option<String>) -> Vec<String> {
+        [
+            filename.or_else(|| self.output.clone()),
+            self.size.clone(),
+            self.upper_left.clone(),
+            self.lower_right.clone(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// The scene that renders the same image as `args`, giving every setting explicitly.
+    /// The corners are written with every digit that matters, so that the image can be
+    /// rendered again exactly.
+    pub fn describe(args: &Arguments) -> Scene {
+        let view = &args.view;
+        let corner = |exact: &FixedComplex, float: Complex<f64>| {
+            format!(
+                "{},{}",
+                decimal(&exact.re, float.re),
+                decimal(&exact.im, float.im)
+            )
+        };
+        let coloring = &args.coloring;
+        let palette = Palette::builtin_names()
+            .iter()
+            .find(|name| Palette::builtin(name).as_ref() == Some(&coloring.palette));
+        let shading = match coloring.shading {
+            Shading::Banded => Some("banded"),
+            Shading::Smooth => Some("smooth"),
+            Shading::Distance => Some("distance"),
+            Shading::LineArt => Some("line-art"),
+            Shading::Trap(_) => None,
+        };
+        Scene {
+            output: Some(args.filename.clone()),
+            size: Some(format!("{}x{}", args.bounds.0, args.bounds.1)),
+            upper_left: Some(corner(&view.upper_left_exact, view.upper_left)),
+            lower_right: Some(corner(&view.lower_right_exact, view.lower_right)),
+            rotate: (view.rotation != 0.0).then(|| degrees(view.rotation)),
+            formula: Some(args.fractal.formula.to_string()),
+            julia: match args.fractal.mode {
+                Mode::Mandelbrot => None,
+                Mode::Julia(c) => Some(format!("{},{}", c.re, c.im)),
+            },
+            max_iter: Some(args.fractal.limit as u32),
+            bailout: Some(args.fractal.bailout),
+            palette: palette.map(|name| name.to_string()),
+            stops: match palette {
+                Some(_) => None,
+                None => Some(coloring.palette.to_string()),
+            },
+            shading: shading.map(str::to_string),
+            trap: coloring.shading.trap().map(|trap| trap.to_string()),
+            histogram: Some(coloring.histogram.is_some()),
+            supersample: Some(args.supersample),
+            format: (args.format != ImageFormat::from_filename(&args.filename))
+                .then(|| args.format.name().to_string()),
+            ..Scene::default()
+        }
+    }
+}
+
+#[test]
+fn test_parse_scene() {
+    let toml = "output = \"mandel.png\"\n\
+                size = \"1000x750\"\n\
+                upper-left = \"-1.20,0.35\"\n\
+                lower-right = \"-1,0.20\"\n\
+                max-iter = 1000\n\
+                palette = \"fire\"\n\
+                shading = \"smooth\"\n\
+                histogram = true\n";
+    let json = r#"{
+        "output": "mandel.png",
+        "size": "1000x750",
+        "upper-left": "-1.20,0.35",
+        "lower-right": "-1,0.20",
+        "max-iter": 1000,
+        "palette": "fire",
+        "shading": "smooth",
+        "histogram": true
+    }"#;
+    let scene = Scene::parse(toml, false).unwrap();
+    assert_eq!(Scene::parse(json, true), Ok(scene.clone()));
+    assert_eq!(
+        scene.options(),
+        [
+            "--max-iter",
+            "1000",
+            "--palette",
+            "fire",
+            "--smooth",
+            "--histogram"
+        ]
+    );
+    assert_eq!(
+        scene.arguments(None),
+        ["mandel.png", "1000x750", "-1.20,0.35", "-1,0.20"]
+    );
+    assert_eq!(
+        scene.arguments(Some("other.png".to_string()))[0],
+        "other.png"
+    );
+    assert_eq!(scene.stops(), None);
+
+    let stops = Scene::parse("stops = \"0 #000000\\n1 #ffffff\\n\"", false).unwrap();
+    assert_eq!(stops.stops().unwrap().color(Some(1.0)).r, 255);
+    for bad in [
+        "sides = 3",
+        "max-iter = \"many\"",
+        "palette = \"fire\"\ngradient = \"g.txt\"",
+        "stops = \"0.5 white\"",
+        "shading = \"fuzzy\"",
+        "shading = \"smooth\"\ntrap = \"point:0,0\"",
+    ] {
+        assert!(Scene::parse(bad, false).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn test_save_scene() {
+    let scene = Scene {
+        output: Some("a.png".to_string()),
+        center: Some("-0.75,0.1".to_string()),
+        zoom: Some(1e6),
+        rotate: Some(-30.0),
+        stops: Some(Palette::builtin("ultra").unwrap().to_string()),
+        ..Scene::default()
+    };
+    // Name the files after this process, so that test runs at the same time don't
+    // overwrite each other's, and remove each before checking what was read from it.
+    for extension in ["toml", "json"] {
+        let name = format!("mandelbrot-test-scene-{}.{}", std::process::id(), extension);
+        let filename = std::env::temp_dir().join(name);
+        let filename = filename.to_str().unwrap();
+        scene.save(filename).unwrap();
+        let loaded = Scene::load(filename);
+        fs::remove_file(filename).unwrap();
+        assert_eq!(loaded.unwrap(), scene);
+    }
+}
+
+/// Write `exact` in decimal, as briefly as possible while still reading back as the same
+/// `Fixed`, with the same fraction bits, and as `float` when rounded to an f64: the same
+/// digits a user gave, often. Failing that, write every digit of it.
+fn decimal(exact: &Fixed, float: f64) -> String {
+    (0..=exact.bits() as usize)
+        .map(|digits| exact.to_decimal(digits))
+        .find(|text| Fixed::from_str(text).as_ref() == Ok(exact) && text.parse() == Ok(float))
+        .unwrap_or_else(|| exact.to_string())
+}
+
+#[test]
+fn test_decimal() {
+    for text in [
+        "-1.20",
+        "0.35",
+        "-1",
+        "-0.743643887037158704752191506114774",
+    ] {
+        let exact = Fixed::from_str(text).unwrap();
+        assert_eq!(decimal(&exact, text.parse().unwrap()), text);
+    }
+    // A value no decimal with so few bits can reach is written out in full.
+    let exact = Fixed::from_f64(0.1, 70);
+    let text = decimal(&exact, 0.1);
+    assert_eq!(text, exact.to_string());
+    assert_eq!(text.parse(), Ok(0.1));
+    assert_eq!(Fixed::from_str(&text).unwrap().with_bits(70), exact);
+}
+
+/// The number of degrees that `--rotate` turns into exactly `radians`. Converting
+/// `radians` to degrees may land an f64 or two away from it, so look nearby.
+fn degrees(radians: f64) -> f64 {
+    let estimate = radians.to_degrees();
+    let (mut below, mut above) = (estimate, estimate);
+    for _ in 0..4 {
+        for degrees in [below, above] {
+            if degrees.to_radians() == radians {
+                return degrees;
+            }
+        }
+        below = below.next_down();
+        above = above.next_up();
+    }
+    estimate
+}
+
+#[test]
+fn test_degrees() {
+    for d in [30.0, 45.5, -123.456, 1e-3, 359.99999, 0.1] {
+        let radians: f64 = f64::to_radians(d);
+        assert_eq!(degrees(radians).to_radians(), radians);
+    }
+}
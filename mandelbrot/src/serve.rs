@@ -0,0 +1,262 @@
+use crate::pyramid::{self, TILE_SIZE};
+use crate::Arguments;
+use crossbeam::channel;
+use image::png::PNGEncoder;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+/// The number of rendered tiles the server keeps by default, about 100 MB of PNGs.
+pub const DEFAULT_CACHE_TILES: usize = 2048;
+
+/// The number of connections answered at once. Further connections wait to be accepted
+/// until one of these is done.
+const CONNECTION_THREADS: usize = 16;
+
+/// How long a connection may take to send its request, or to accept the response, before
+/// it is dropped, so that idle clients can't hold on to a connection thread.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most bytes of request line and headers read from a connection.
+const MAX_REQUEST_BYTES: u64 = 16 * 1024;
+
+/// The viewer page served at `/`: a self-contained slippy map, so it works offline.
+const VIEWER: &str = include_str!("viewer.html");
+
+/// A tile's zoom level, column and row.
+type TileKey = (usize, usize, usize);
+
+/// Encoded tiles, keeping the most recently used ones once full.
+struct TileCache {
+    capacity: usize,
+    /// Each tile's PNG, and the `clock` reading when it was last used.
+    tiles: HashMap<TileKey, (Vec<u8>, u64)>,
+    clock: u64,
+}
+
+impl TileCache {
+    fn new(capacity: usize) -> TileCache {
+        TileCache {
+            capacity,
+            tiles: HashMap::new(),
+            clock: 0,
+        }
+    }
+
+    fn get(&mut self, key: TileKey) -> Option<Vec<u8>> {
+        self.clock += 1;
+        let (png, used) = self.tiles.get_mut(&key)?;
+        *used = self.clock;
+        Some(png.clone())
+    }
+
+    /// Add a tile, first evicting the least recently used one if the cache is full. The
+    /// search for it takes time in proportion to the capacity, which is nothing next to
+    /// rendering a tile.
+    fn insert(&mut self, key: TileKey, png: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.tiles.len() >= self.capacity && !self.tiles.contains_key(&key) {
+            let oldest = self.tiles.iter().min_by_key(|(_, &(_, used))| used);
+            let oldest = *oldest.unwrap().0;
+            self.tiles.remove(&oldest);
+        }
+        self.clock += 1;
+        self.tiles.insert(key, (png, self.clock));
+    }
+}
+
+#[test]
+fn test_tile_cache() {
+    let mut cache = TileCache::new(2);
+    cache.insert((0, 0, 0), vec![0]);
+    cache.insert((1, 0, 0), vec![1]);
+    assert_eq!(cache.get((0, 0, 0)), Some(vec![0]));
+    // (1, 0, 0) is now the least recently used, so it makes way.
+    cache.insert((1, 1, 0), vec![2]);
+    assert_eq!(cache.get((1, 0, 0)), None);
+    assert_eq!(cache.get((0, 0, 0)), Some(vec![0]));
+    assert_eq!(cache.get((1, 1, 0)), Some(vec![2]));
+    assert_eq!(cache.tiles.len(), 2);
+}
+
+/// The tile a request path such as `/tiles/3/5/7.png` asks for, if it is one of the
+/// pyramid's.
+fn parse_tile_path(path: &str) -> Option<TileKey> {
+    let rest = path.strip_prefix("/tiles/")?.strip_suffix(".png")?;
+    let mut parts = rest.split('/').map(|part| part.parse::<usize>().ok());
+    let (level, x, y) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || level > pyramid::MAX_LEVEL || x >> level > 0 || y >> level > 0 {
+        return None;
+    }
+    Some((level, x, y))
+}
+
+#[test]
+fn test_parse_tile_path() {
+    assert_eq!(parse_tile_path("/tiles/3/5/7.png"), Some((3, 5, 7)));
+    assert_eq!(parse_tile_path("/tiles/0/0/0.png"), Some((0, 0, 0)));
+    assert_eq!(parse_tile_path("/tiles/1/2/0.png"), None);
+    assert_eq!(parse_tile_path("/tiles/3/5.png"), None);
+    assert_eq!(parse_tile_path("/tiles/3/5/7/1.png"), None);
+    assert_eq!(parse_tile_path("/tiles/a/5/7.png"), None);
+    assert_eq!(parse_tile_path("/tiles/99/0/0.png"), None);
+    assert_eq!(parse_tile_path("/other/3/5/7.png"), None);
+}
+
+/// Render the tile `key` of the pyramid over `args.view`, and return it as a PNG.
+fn render_tile(args: &Arguments, key: TileKey) -> Result<Vec<u8>, io::Error> {
+    let (level, x, y) = key;
+    let view = pyramid::tile_view(&args.view, level, x, y);
+    let bounds = (TILE_SIZE * args.supersample, TILE_SIZE * args.supersample);
+    let grid = crate::render_grid(args, &view, bounds);
+    let mut png = Vec::new();
+    PNGEncoder::new(&mut png).encode(
+        &crate::color_image(&grid, &args.coloring),
+        TILE_SIZE as u32,
+        TILE_SIZE as u32,
+        args.coloring.palette.color_type(),
+    )?;
+    Ok(png)
+}
+
+/// The state the connection threads share: the tile cache, and a lock held while
+/// rendering, since each render already keeps every worker thread busy.
+struct Server {
+    cache: Mutex<TileCache>,
+    rendering: Mutex<()>,
+}
+
+/// Serve the viewer page and the tiles of an endless pyramid over `args.view` at
+/// `address`, such as `127.0.0.1:8080`, rendering tiles as they are asked for. A pool of
+/// `CONNECTION_THREADS` threads answers the connections, so cached tiles are still
+/// served while a slow one renders.
+pub fn serve(args: &Arguments, address: &str) -> Result<(), io::Error> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("serving on http://{}/", listener.local_addr()?);
+    let server = Server {
+        cache: Mutex::new(TileCache::new(args.cache_tiles)),
+        rendering: Mutex::new(()),
+    };
+    // With no room in the channel, a connection is only accepted once a thread is free.
+    let (sender, receiver) = channel::bounded::<TcpStream>(0);
+    crossbeam::scope(|spawner| {
+        for _ in 0..CONNECTION_THREADS {
+            let (receiver, server) = (receiver.clone(), &server);
+            spawner.spawn(move |_| {
+                for stream in receiver {
+                    // A panic while answering one request drops that connection, but
+                    // leaves the thread to serve the next.
+                    let answered =
+                        panic::catch_unwind(AssertUnwindSafe(|| respond(args, server, stream)));
+                    match answered.unwrap_or(Ok(())) {
+                        // A client that went quiet is simply dropped.
+                        Err(e)
+                            if matches!(
+                                e.kind(),
+                                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                            ) => {}
+                        Err(e) => eprintln!("error answering request: {}", e),
+                        Ok(()) => {}
+                    }
+                }
+            });
+        }
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => sender.send(stream).unwrap(),
+                Err(e) => eprintln!("error accepting connection: {}", e),
+            }
+        }
+    })
+    .unwrap();
+    Ok(())
+}
+
+/// Read one request from `stream` and answer it.
+fn respond(args: &Arguments, server: &Server, mut stream: TcpStream) -> Result<(), io::Error> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_REQUEST_BYTES));
+    let mut request = String::new();
+    if reader.read_line(&mut request)? == 0 {
+        return Ok(());
+    }
+    // Skip the headers, up to the blank line that ends them or the end of the stream;
+    // nothing in them changes the answer.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+
+    let mut words = request.split_whitespace();
+    let (method, path) = (words.next(), words.next().unwrap_or(""));
+    // Ignore any query string, such as a viewer's cache-busting one.
+    let path = path.split('?').next().unwrap();
+    if method != Some("GET") {
+        return write_response(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+    if path == "/" {
+        return write_response(&mut stream, "200 OK", "text/html", VIEWER.as_bytes());
+    }
+    if path == "/pyramid.json" {
+        let manifest = pyramid::manifest(&args.view, pyramid::MAX_LEVEL);
+        return write_response(
+            &mut stream,
+            "200 OK",
+            "application/json",
+            manifest.as_bytes(),
+        );
+    }
+    let key = match parse_tile_path(path) {
+        Some(key) => key,
+        None => return write_response(&mut stream, "404 Not Found", "text/plain", b""),
+    };
+    let cached = lock(&server.cache).get(key);
+    let png = match cached {
+        Some(png) => png,
+        None => {
+            // Render without holding the cache's lock, so cached tiles can be served
+            // meanwhile. Another connection may have rendered the tile while this one
+            // waited its turn.
+            let _rendering = lock(&server.rendering);
+            let cached = lock(&server.cache).get(key);
+            match cached {
+                Some(png) => png,
+                None => {
+                    let png = render_tile(args, key)?;
+                    lock(&server.cache).insert(key, png.clone());
+                    png
+                }
+            }
+        }
+    };
+    write_response(&mut stream, "200 OK", "image/png", &png)
+}
+
+/// Lock `mutex`, even if a thread panicked while holding it: the tile cache is
+/// consistent between calls, and the render lock guards no data at all.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<(), io::Error> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
@@ -0,0 +1,298 @@
+use crate::formula::Formula;
+use crate::{escape_time, Escape, Tracking, EXTRA_ITERATIONS};
+use num::Complex;
+
+/// The instruction set `escape_times` will use on this machine.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Kernel {
+    /// One point at a time, with `escape_time`.
+    Scalar,
+    /// Two points at a time, with SSE2.
+    Sse2,
+    /// Four points at a time, with AVX2.
+    Avx2,
+}
+
+impl Kernel {
+    /// The widest kernel this CPU supports.
+    pub fn detect() -> Kernel {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if is_x86_feature_detected!("avx2") {
+                return Kernel::Avx2;
+            }
+            if is_x86_feature_detected!("sse2") {
+                return Kernel::Sse2;
+            }
+        }
+        Kernel::Scalar
+    }
+
+    /// How many points the kernel iterates at once.
+    fn lanes(&self) -> usize {
+        match self {
+            Kernel::Scalar => 1,
+            Kernel::Sse2 => 2,
+            Kernel::Avx2 => 4,
+        }
+    }
+
+    /// Iterate `lanes()` points at once with a vector kernel.
+    fn iterate(
+        &self,
+        zs: &[Complex<f64>],
+        cs: &[Complex<f64>],
+        limit: usize,
+        bailout: f64,
+    ) -> LaneEscapes {
+        assert!(zs.len() == self.lanes() && cs.len() == self.lanes());
+        match self {
+            // Safe because `detect` only returns these kernels if the CPU supports them.
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Sse2 => unsafe { x86::escape_times_sse2(zs, cs, limit, bailout) },
+            #[cfg(target_arch = "x86_64")]
+            Kernel::Avx2 => unsafe { x86::escape_times_avx2(zs, cs, limit, bailout) },
+            _ => unreachable!("no vector kernel for {:?}", self),
+        }
+    }
+}
+
+/// Compute `escape_time(&Formula::Mandelbrot, zs[i], cs[i], Tracking::NONE, limit, bailout)` for
+/// every `i`, storing the results in `escapes`, several points at a time with `kernel`.
+///
+/// The results are exactly those `escape_time` would produce: the vector kernels perform
+/// the same floating-point operations in the same order, without fused multiply-adds.
+pub fn escape_times(
+    kernel: Kernel,
+    zs: &[Complex<f64>],
+    cs: &[Complex<f64>],
+    limit: usize,
+    bailout: f64,
+    escapes: &mut [Option<Escape>],
+) {
+    assert!(zs.len() == cs.len() && zs.len() == escapes.len());
+    let lanes = kernel.lanes();
+    let vectorized = match kernel {
+        Kernel::Scalar => 0,
+        _ => zs.len() / lanes * lanes,
+    };
+    for start in (0..vectorized).step_by(lanes) {
+        let range = start..start + lanes;
+        let lane_escapes = kernel.iterate(&zs[range.clone()], &cs[range], limit, bailout);
+        for (lane, escape) in lane_escapes.iter().take(lanes).enumerate() {
+            let c = cs[start + lane];
+            escapes[start + lane] = escape.map(|(count, mut z)| {
+                for _ in 0..EXTRA_ITERATIONS {
+                    z = z * z + c;
+                }
+                Escape {
+                    count,
+                    z,
+                    derivative: None,
+                    trap: None,
+                }
+            });
+        }
+    }
+    for i in vectorized..zs.len() {
+        escapes[i] = escape_time(
+            &Formula::Mandelbrot,
+            zs[i],
+            cs[i],
+            Tracking::NONE,
+            limit,
+            bailout,
+        );
+    }
+}
+
+/// For each lane, the iteration at which it escaped and its `z` at that point.
+type LaneEscapes = [Option<(usize, Complex<f64>)>; 4];
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::LaneEscapes;
+    use crate::PERIODICITY_TOLERANCE;
+    use num::Complex;
+    use std::arch::x86_64::*;
+
+    /// Iterate two points at once. `zs` and `cs` must hold exactly two points.
+    #[target_feature(enable = "sse2")]
+    pub unsafe fn escape_times_sse2(
+        zs: &[Complex<f64>],
+        cs: &[Complex<f64>],
+        limit: usize,
+        bailout: f64,
+    ) -> LaneEscapes {
+        let mut escapes: LaneEscapes = [None; 4];
+        let mut zr = _mm_set_pd(zs[1].re, zs[0].re);
+        let mut zi = _mm_set_pd(zs[1].im, zs[0].im);
+        let cr = _mm_set_pd(cs[1].re, cs[0].re);
+        let ci = _mm_set_pd(cs[1].im, cs[0].im);
+        let bailout = _mm_set1_pd(bailout * bailout);
+        let tolerance = _mm_set1_pd(PERIODICITY_TOLERANCE);
+        let (mut saved_r, mut saved_i) = (zr, zi);
+        let mut next_save = 1;
+        let mut active = 0b11;
+        for i in 0..limit {
+            let norm = _mm_add_pd(_mm_mul_pd(zr, zr), _mm_mul_pd(zi, zi));
+            let escaped = _mm_movemask_pd(_mm_cmpgt_pd(norm, bailout)) & active;
+            if escaped != 0 {
+                let (mut re, mut im) = ([0.0; 2], [0.0; 2]);
+                _mm_storeu_pd(re.as_mut_ptr(), zr);
+                _mm_storeu_pd(im.as_mut_ptr(), zi);
+                for lane in 0..2 {
+                    if escaped & (1 << lane) != 0 {
+                        escapes[lane] = Some((
+                            i,
+                            Complex {
+                                re: re[lane],
+                                im: im[lane],
+                            },
+                        ));
+                    }
+                }
+                active &= !escaped;
+                if active == 0 {
+                    break;
+                }
+            }
+            let re = _mm_add_pd(_mm_sub_pd(_mm_mul_pd(zr, zr), _mm_mul_pd(zi, zi)), cr);
+            let im = _mm_add_pd(_mm_add_pd(_mm_mul_pd(zr, zi), _mm_mul_pd(zi, zr)), ci);
+            zr = re;
+            zi = im;
+
+            // Lanes whose orbits have returned to their saved value never escape.
+            let (dr, di) = (_mm_sub_pd(zr, saved_r), _mm_sub_pd(zi, saved_i));
+            let distance = _mm_add_pd(_mm_mul_pd(dr, dr), _mm_mul_pd(di, di));
+            active &= !_mm_movemask_pd(_mm_cmplt_pd(distance, tolerance));
+            if active == 0 {
+                break;
+            }
+            if i + 1 == next_save {
+                saved_r = zr;
+                saved_i = zi;
+                next_save *= 2;
+            }
+        }
+        escapes
+    }
+
+    /// Iterate four points at once. `zs` and `cs` must hold exactly four points.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn escape_times_avx2(
+        zs: &[Complex<f64>],
+        cs: &[Complex<f64>],
+        limit: usize,
+        bailout: f64,
+    ) -> LaneEscapes {
+        let mut escapes: LaneEscapes = [None; 4];
+        let mut zr = _mm256_set_pd(zs[3].re, zs[2].re, zs[1].re, zs[0].re);
+        let mut zi = _mm256_set_pd(zs[3].im, zs[2].im, zs[1].im, zs[0].im);
+        let cr = _mm256_set_pd(cs[3].re, cs[2].re, cs[1].re, cs[0].re);
+        let ci = _mm256_set_pd(cs[3].im, cs[2].im, cs[1].im, cs[0].im);
+        let bailout = _mm256_set1_pd(bailout * bailout);
+        let tolerance = _mm256_set1_pd(PERIODICITY_TOLERANCE);
+        let (mut saved_r, mut saved_i) = (zr, zi);
+        let mut next_save = 1;
+        let mut active = 0b1111;
+        for i in 0..limit {
+            let norm = _mm256_add_pd(_mm256_mul_pd(zr, zr), _mm256_mul_pd(zi, zi));
+            let escaped = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_GT_OQ>(norm, bailout)) & active;
+            if escaped != 0 {
+                let (mut re, mut im) = ([0.0; 4], [0.0; 4]);
+                _mm256_storeu_pd(re.as_mut_ptr(), zr);
+                _mm256_storeu_pd(im.as_mut_ptr(), zi);
+                for lane in 0..4 {
+                    if escaped & (1 << lane) != 0 {
+                        escapes[lane] = Some((
+                            i,
+                            Complex {
+                                re: re[lane],
+                                im: im[lane],
+                            },
+                        ));
+                    }
+                }
+                active &= !escaped;
+                if active == 0 {
+                    break;
+                }
+            }
+            let re = _mm256_add_pd(
+                _mm256_sub_pd(_mm256_mul_pd(zr, zr), _mm256_mul_pd(zi, zi)),
+                cr,
+            );
+            let im = _mm256_add_pd(
+                _mm256_add_pd(_mm256_mul_pd(zr, zi), _mm256_mul_pd(zi, zr)),
+                ci,
+            );
+            zr = re;
+            zi = im;
+
+            // Lanes whose orbits have returned to their saved value never escape.
+            let (dr, di) = (_mm256_sub_pd(zr, saved_r), _mm256_sub_pd(zi, saved_i));
+            let distance = _mm256_add_pd(_mm256_mul_pd(dr, dr), _mm256_mul_pd(di, di));
+            active &= !_mm256_movemask_pd(_mm256_cmp_pd::<_CMP_LT_OQ>(distance, tolerance));
+            if active == 0 {
+                break;
+            }
+            if i + 1 == next_save {
+                saved_r = zr;
+                saved_i = zi;
+                next_save *= 2;
+            }
+        }
+        escapes
+    }
+}
+
+#[test]
+fn test_kernels_match_scalar() {
+    // A grid over the whole set, plus an odd count so the scalar tail is exercised.
+    let mut cs = Vec::new();
+    for y in 0..61 {
+        for x in 0..83 {
+            cs.push(Complex {
+                re: -2.2 + x as f64 * 0.034,
+                im: -1.2 + y as f64 * 0.04,
+            });
+        }
+    }
+    let zs = vec![Complex { re: 0.0, im: 0.0 }; cs.len()];
+    let expected: Vec<Option<Escape>> = zs
+        .iter()
+        .zip(&cs)
+        .map(|(&z, &c)| escape_time(&Formula::Mandelbrot, z, c, Tracking::NONE, 255, 2.0))
+        .collect();
+    let mut kernels = vec![Kernel::Scalar];
+    #[cfg(target_arch = "x86_64")]
+    {
+        if is_x86_feature_detected!("sse2") {
+            kernels.push(Kernel::Sse2);
+        }
+        if is_x86_feature_detected!("avx2") {
+            kernels.push(Kernel::Avx2);
+        }
+    }
+    for kernel in kernels {
+        let mut escapes = vec![None; cs.len()];
+        escape_times(kernel, &zs, &cs, 255, 2.0, &mut escapes);
+        assert_eq!(escapes, expected, "{:?}", kernel);
+
+        // Julia-style starting points too.
+        let c = vec![
+            Complex {
+                re: -0.8,
+                im: 0.156
+            };
+            cs.len()
+        ];
+        let expected: Vec<Option<Escape>> = cs
+            .iter()
+            .map(|&z| escape_time(&Formula::Mandelbrot, z, c[0], Tracking::NONE, 100, 10.0))
+            .collect();
+        escape_times(kernel, &cs, &c, 100, 10.0, &mut escapes);
+        assert_eq!(escapes, expected, "{:?}", kernel);
+    }
+}
@@ -0,0 +1,56 @@
+/// The largest supersampling factor accepted. The sums of a block of 16-bit values would
+/// overflow a u32 above 256; far fewer samples than that already give a smooth edge.
+pub const MAX_FACTOR: usize = 16;
+
+/// Shrink `pixels`, an image of the given `bounds` with `channels` values per pixel, by
+/// `factor` in each direction, averaging each `factor` × `factor` block of pixels into
+/// one. Both of the bounds must be multiples of `factor`. The values are 8-bit or
+/// 16-bit channels.
+pub fn downsample<T>(pixels: &[T], bounds: (usize, usize), channels: usize, factor: usize) -> Vec<T>
+where
+    T: Copy + Into<u32> + TryFrom<u32>,
+{
+    assert!(factor <= MAX_FACTOR);
+    assert!(bounds.0.is_multiple_of(factor) && bounds.1.is_multiple_of(factor));
+    assert!(pixels.len() == bounds.0 * bounds.1 * channels);
+    let (width, height) = (bounds.0 / factor, bounds.1 / factor);
+    let count = (factor * factor) as u32;
+    let mut sums = vec![0u32; width * channels];
+    let mut output = Vec::with_capacity(width * height * channels);
+    for block_row in pixels.chunks(bounds.0 * channels * factor) {
+        sums.iter_mut().for_each(|sum| *sum = 0);
+        for row in block_row.chunks(bounds.0 * channels) {
+            for (x, pixel) in row.chunks(channels).enumerate() {
+                let sum = &mut sums[x / factor * channels..][..channels];
+                for (sum, &value) in sum.iter_mut().zip(pixel) {
+                    *sum += value.into();
+                }
+            }
+        }
+        // An average never exceeds the largest value averaged, so it always fits.
+        output.extend(
+            sums.iter()
+                .map(|&sum| T::try_from((sum + count / 2) / count).ok().unwrap()),
+        );
+    }
+    output
+}
+
+#[test]
+fn test_downsample() {
+    // A 4x2 image with two channels per pixel, shrunk to 2x1.
+    let pixels: [u8; 16] = [
+        0, 10, 255, 20, 7, 1, 7, 1, // first row
+        255, 30, 255, 40, 7, 1, 8, 2, // second row
+    ];
+    assert_eq!(downsample(&pixels, (4, 2), 2, 2), [191, 25, 7, 1]);
+    assert_eq!(downsample(&pixels, (4, 2), 2, 1), pixels);
+    let wide: [u16; 4] = [65535, 65534, 0, 1];
+    assert_eq!(downsample(&wide, (2, 2), 1, 2), [32768]);
+    // The largest factor can't overflow the sums, even at the brightest 16-bit value.
+    let white = vec![65535u16; MAX_FACTOR * MAX_FACTOR];
+    assert_eq!(
+        downsample(&white, (MAX_FACTOR, MAX_FACTOR), 1, MAX_FACTOR),
+        [65535]
+    );
+}
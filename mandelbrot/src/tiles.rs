@@ -0,0 +1,142 @@
+use crossbeam::channel;
+
+/// The width and height of the tiles `render_tiles` divides an image into.
+pub const TILE_SIZE: usize = 64;
+
+/// A rectangle of pixels within an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tile {
+    pub left: usize,
+    pub top: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+/// Cover an image of the given `bounds` with tiles at most `size.0` by `size.1` pixels,
+/// in row-major order. Tiles at the right and bottom edges may be smaller.
+pub fn tiles(bounds: (usize, usize), size: (usize, usize)) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for top in (0..bounds.1).step_by(size.1) {
+        for left in (0..bounds.0).step_by(size.0) {
+            tiles.push(Tile {
+                left,
+                top,
+                width: size.0.min(bounds.0 - left),
+                height: size.1.min(bounds.1 - top),
+            });
+        }
+    }
+    tiles
+}
+
+#[test]
+fn test_tiles() {
+    let covered = tiles((150, 70), (64, 64));
+    assert_eq!(covered.len(), 6);
+    assert_eq!(
+        covered[2],
+        Tile {
+            left: 128,
+            top: 0,
+            width: 22,
+            height: 64
+        }
+    );
+    assert_eq!(
+        covered[5],
+        Tile {
+            left: 128,
+            top: 64,
+            width: 22,
+            height: 6
+        }
+    );
+    let area: usize = covered.iter().map(|t| t.width * t.height).sum();
+    assert_eq!(area, 150 * 70);
+    assert!(tiles((0, 0), (64, 64)).is_empty());
+}
+
+/// The number of worker threads to use when none is specified: one per available CPU.
+pub fn default_threads() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Render `pixels`, an image of the given `bounds` with one `T` per pixel, on `threads`
+/// worker threads. The image is divided into `tiles`, which the workers take
+/// from a shared queue one at a time, so no worker sits idle while tiles remain, however
+/// unevenly the work is spread over the image.
+///
+/// For each tile, `render_tile(buffer, tile)` must fill `buffer` with the tile's pixels in
+/// row-major order; they are then copied into place in `pixels`.
+pub fn render_tiles<T, F>(
+    pixels: &mut [T],
+    bounds: (usize, usize),
+    tiles: &[Tile],
+    threads: usize,
+    render_tile: F,
+) where
+    T: Copy + Default + Send,
+    F: Fn(&mut [T], Tile) + Sync,
+{
+    assert!(pixels.len() == bounds.0 * bounds.1);
+    assert!(threads > 0);
+
+    let (tile_sender, tile_receiver) = channel::unbounded();
+    for &tile in tiles {
+        tile_sender.send(tile).unwrap();
+    }
+    drop(tile_sender);
+    let (done_sender, done_receiver) = channel::unbounded::<(Tile, Vec<T>)>();
+
+    let render_tile = &render_tile;
+    crossbeam::scope(|spawner| {
+        for _ in 0..threads {
+            let tile_receiver = tile_receiver.clone();
+            let done_sender = done_sender.clone();
+            spawner.spawn(move |_| {
+                for tile in tile_receiver {
+                    let mut buffer = vec![T::default(); tile.width * tile.height];
+                    render_tile(&mut buffer, tile);
+                    done_sender.send((tile, buffer)).unwrap();
+                }
+            });
+        }
+        drop(done_sender);
+
+        // Copy each finished tile into the image as it arrives.
+        for (tile, buffer) in done_receiver {
+            for (y, source) in buffer.chunks(tile.width).enumerate() {
+                let start = (tile.top + y) * bounds.0 + tile.left;
+                pixels[start..start + tile.width].copy_from_slice(source);
+            }
+        }
+    })
+    .unwrap();
+}
+
+#[test]
+fn test_render_tiles() {
+    // Fill each pixel with a value computed from its coordinates, and check that every
+    // tile lands in the right place however many workers there are.
+    let bounds = (37, 23);
+    let expected: Vec<(usize, usize)> = (0..bounds.1)
+        .flat_map(|y| (0..bounds.0).map(move |x| (x, y)))
+        .collect();
+    for threads in [1, 3, 8] {
+        let mut pixels = vec![(0, 0); bounds.0 * bounds.1];
+        render_tiles(
+            &mut pixels,
+            bounds,
+            &tiles(bounds, (8, 5)),
+            threads,
+            |buffer, tile| {
+                for y in 0..tile.height {
+                    for x in 0..tile.width {
+                        buffer[y * tile.width + x] = (tile.left + x, tile.top + y);
+                    }
+                }
+            },
+        );
+        assert_eq!(pixels, expected);
+    }
+}
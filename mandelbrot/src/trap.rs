@@ -0,0 +1,143 @@
+use num::Complex;
+use std::fmt;
+use std::str::FromStr;
+
+/// A shape that orbits are measured against for orbit-trap coloring: each pixel is
+/// colored by how close its orbit ever came to the shape.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Trap {
+    Point(Complex<f64>),
+    /// The line through `through` at `degrees` counterclockwise from the real axis, running
+    /// in the direction of the unit vector `direction`.
+    Line {
+        through: Complex<f64>,
+        degrees: f64,
+        direction: Complex<f64>,
+    },
+    Circle {
+        center: Complex<f64>,
+        radius: f64,
+    },
+}
+
+impl Trap {
+    /// The distance from `z` to the nearest point of the trap.
+    pub fn distance(&self, z: Complex<f64>) -> f64 {
+        match *self {
+            Trap::Point(point) => (z - point).norm(),
+            // Turning the offset so the line lies along the real axis leaves the distance
+            // to it in the imaginary part.
+            Trap::Line {
+                through, direction, ..
+            } => ((z - through) * direction.conj()).im.abs(),
+            Trap::Circle { center, radius } => ((z - center).norm() - radius).abs(),
+        }
+    }
+}
+
+#[cfg(test)]
+fn c(re: f64, im: f64) -> Complex<f64> {
+    Complex { re, im }
+}
+
+#[test]
+fn test_trap_distance() {
+    let point = Trap::Point(c(1.0, 1.0));
+    assert_eq!(point.distance(c(4.0, 5.0)), 5.0);
+    assert_eq!(point.distance(c(1.0, 1.0)), 0.0);
+
+    let line: Trap = "line:0,1,45".parse().unwrap();
+    assert!((line.distance(c(1.0, 0.0)) - 2f64.sqrt()).abs() < 1e-12);
+    assert!(line.distance(c(2.0, 3.0)) < 1e-12);
+    let axis: Trap = "line:0,0,0".parse().unwrap();
+    assert_eq!(axis.distance(c(7.0, -0.25)), 0.25);
+
+    let circle = Trap::Circle {
+        center: c(0.0, 0.0),
+        radius: 1.0,
+    };
+    assert_eq!(circle.distance(c(0.0, 0.25)), 0.75);
+    assert_eq!(circle.distance(c(-3.0, 0.0)), 2.0);
+}
+
+impl FromStr for Trap {
+    type Err = String;
+
+    /// Parse `point:RE,IM`; `line:RE,IM,DEGREES`, for the line through `RE,IM` at that
+    /// angle counterclockwise from the real axis; or `circle:RE,IM,RADIUS`.
+    fn from_str(s: &str) -> Result<Trap, String> {
+        let error = || format!("error parsing trap '{}'", s);
+        let (shape, numbers) = s.split_once(':').ok_or_else(error)?;
+        let numbers: Vec<f64> = numbers
+            .split(',')
+            .map(|n| n.parse().ok().filter(|n: &f64| n.is_finite()))
+            .collect::<Option<_>>()
+            .ok_or_else(error)?;
+        match (shape, &numbers[..]) {
+            ("point", &[re, im]) => Ok(Trap::Point(Complex { re, im })),
+            ("line", &[re, im, degrees]) => Ok(Trap::Line {
+                through: Complex { re, im },
+                degrees,
+                direction: Complex::from_polar(1.0, degrees.to_radians()),
+            }),
+            ("circle", &[re, im, radius]) if radius > 0.0 => Ok(Trap::Circle {
+                center: Complex { re, im },
+                radius,
+            }),
+            ("point" | "line" | "circle", _) => Err(error()),
+            _ => Err(format!("unknown trap shape '{}'", shape)),
+        }
+    }
+}
+
+impl fmt::Display for Trap {
+    /// Write the trap as `FromStr` parses it.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Trap::Point(point) => write!(f, "point:{},{}", point.re, point.im),
+            Trap::Line {
+                through, degrees, ..
+            } => write!(f, "line:{},{},{}", through.re, through.im, degrees),
+            Trap::Circle { center, radius } => {
+                write!(f, "circle:{},{},{}", center.re, center.im, radius)
+            }
+        }
+    }
+}
+
+#[test]
+fn test_parse_trap() {
+    assert_eq!("point:0.5,-1".parse(), Ok(Trap::Point(c(0.5, -1.0))));
+    assert_eq!(
+        "circle:0,0,0.25".parse(),
+        Ok(Trap::Circle {
+            center: c(0.0, 0.0),
+            radius: 0.25
+        })
+    );
+    match "line:1,2,90".parse() {
+        Ok(Trap::Line {
+            through,
+            degrees,
+            direction,
+        }) => {
+            assert_eq!(through, c(1.0, 2.0));
+            assert_eq!(degrees, 90.0);
+            assert!((direction - c(0.0, 1.0)).norm() < 1e-12);
+        }
+        other => panic!("{:?}", other),
+    }
+    for bad in [
+        "point:1",
+        "line:0,0",
+        "circle:0,0,-1",
+        "point",
+        "point:a,b",
+        "star:0,0",
+    ] {
+        assert!(bad.parse::<Trap>().is_err(), "{}", bad);
+    }
+    for spec in ["point:0.5,-1", "line:1,2,22.5", "circle:0,0,0.25"] {
+        assert_eq!(spec.parse::<Trap>().unwrap().to_string(), spec);
+    }
+}
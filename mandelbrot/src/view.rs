@@ -0,0 +1,310 @@
+use crate::fixed::{Fixed, FixedComplex};
+use crate::tiles::Tile;
+use num::Complex;
+
+/// Two views whose pixel spacings differ by less than this fraction are taken to have
+/// square pixels already.
+const ASPECT_TOLERANCE: f64 = 1e-9;
+
+/// The region of the complex plane an image shows.
+#[derive(Clone, Debug, PartialEq)]
+pub struct View {
+    /// The corners of the view before any rotation, rounded to f64.
+    pub upper_left: Complex<f64>,
+    pub lower_right: Complex<f64>,
+    /// The same corners with every digit given, for deep zooms.
+    pub upper_left_exact: FixedComplex,
+    pub lower_right_exact: FixedComplex,
+    /// How far the view is turned counterclockwise about its center, in radians.
+    pub rotation: f64,
+}
+
+impl View {
+    /// The view with the given corners, such as `"-1.20,0.35"` and `"-1,0.20"`.
+    pub fn corners(upper_left: &str, lower_right: &str) -> Option<View> {
+        Some(View {
+            upper_left: crate::parse_complex(upper_left)?,
+            lower_right: crate::parse_complex(lower_right)?,
+            upper_left_exact: crate::parse_fixed_complex(upper_left)?,
+            lower_right_exact: crate::parse_fixed_complex(lower_right)?,
+            rotation: 0.0,
+        })
+    }
+
+    /// The view of an image with the given `bounds`, centered on `center`, reaching
+    /// `radius` from the center to the nearest edge.
+    pub fn centered(center: &FixedComplex, radius: f64, bounds: (usize, usize)) -> View {
+        let aspect = bounds.0 as f64 / bounds.1 as f64;
+        let (half_width, half_height) = if aspect >= 1.0 {
+            (radius * aspect, radius)
+        } else {
+            (radius, radius / aspect)
+        };
+        // Enough bits to hold the half-sizes exactly, as well as every digit of the center.
+        let bits = center
+            .re
+            .bits()
+            .max((-half_width.min(half_height).log2()).ceil().max(0.0) as u32 + 64);
+        let center = center.with_bits(bits);
+        let half_width = Fixed::from_f64(half_width, bits);
+        let half_height = Fixed::from_f64(half_height, bits);
+        View::from_exact(
+            FixedComplex {
+                re: &center.re - &half_width,
+                im: &center.im + &half_height,
+            },
+            FixedComplex {
+                re: &center.re + &half_width,
+                im: &center.im - &half_height,
+            },
+        )
+    }
+
+    fn from_exact(upper_left: FixedComplex, lower_right: FixedComplex) -> View {
+        View {
+            upper_left: upper_left.to_complex(),
+            lower_right: lower_right.to_complex(),
+            upper_left_exact: upper_left,
+            lower_right_exact: lower_right,
+            rotation: 0.0,
+        }
+    }
+
+    /// Widen or heighten the view about its center as needed to make the pixels of an
+    /// image with the given `bounds` square, so the image isn't stretched.
+    pub fn square_pixels(&self, bounds: (usize, usize)) -> View {
+        let spacing_x = (self.lower_right.re - self.upper_left.re).abs() / bounds.0 as f64;
+        let spacing_y = (self.upper_left.im - self.lower_right.im).abs() / bounds.1 as f64;
+        if (spacing_x - spacing_y).abs() <= ASPECT_TOLERANCE * spacing_x.max(spacing_y) {
+            return self.clone();
+        }
+        let bits = self.bits();
+        let upper_left = self.upper_left_exact.with_bits(bits);
+        let lower_right = self.lower_right_exact.with_bits(bits);
+        let width = &lower_right.re - &upper_left.re;
+        let height = &upper_left.im - &lower_right.im;
+        let center = self.center_exact(bits);
+        let (half_width, half_height) = if spacing_x < spacing_y {
+            (
+                height.abs().scale(bounds.0, 2 * bounds.1),
+                height.abs().scale(1, 2),
+            )
+        } else {
+            (
+                width.abs().scale(1, 2),
+                width.abs().scale(bounds.1, 2 * bounds.0),
+            )
+        };
+        // Keep the corners in the order they were given.
+        let (half_width, half_height) = (
+            if width.to_f64() < 0.0 {
+                -&half_width
+            } else {
+                half_width
+            },
+            if height.to_f64() < 0.0 {
+                -&half_height
+            } else {
+                half_height
+            },
+        );
+        View {
+            rotation: self.rotation,
+            ..View::from_exact(
+                FixedComplex {
+                    re: &center.re - &half_width,
+                    im: &center.im + &half_height,
+                },
+                FixedComplex {
+                    re: &center.re + &half_width,
+                    im: &center.im - &half_height,
+                },
+            )
+        }
+    }
+
+    /// The point at the middle of the view.
+    pub fn center(&self) -> Complex<f64> {
+        (self.upper_left + self.lower_right) / 2.0
+    }
+
+    /// The distance from the center of the view to its nearest edge, as `centered` takes.
+    pub fn radius(&self) -> f64 {
+        let width = (self.lower_right.re - self.upper_left.re).abs();
+        let height = (self.upper_left.im - self.lower_right.im).abs();
+        width.min(height) / 2.0
+    }
+
+    /// The number of fraction bits needed to hold both corners exactly.
+    pub fn bits(&self) -> u32 {
+        self.upper_left_exact
+            .re
+            .bits()
+            .max(self.lower_right_exact.re.bits())
+    }
+
+    /// Turn an offset from the center of the view by the view's rotation.
+    pub fn rotate_offset(&self, offset: Complex<f64>) -> Complex<f64> {
+        if self.rotation == 0.0 {
+            return offset;
+        }
+        offset * Complex::from_polar(1.0, self.rotation)
+    }
+
+    /// Turn `point` about the center of the view by the view's rotation.
+    pub fn rotate(&self, point: Complex<f64>) -> Complex<f64> {
+        if self.rotation == 0.0 {
+            return point;
+        }
+        let center = self.center();
+        center + self.rotate_offset(point - center)
+    }
+
+    /// The point at the middle of the view, with `bits` fraction bits.
+    pub fn center_exact(&self, bits: u32) -> FixedComplex {
+        let upper_left = self.upper_left_exact.with_bits(bits);
+        let lower_right = self.lower_right_exact.with_bits(bits);
+        let width = &lower_right.re - &upper_left.re;
+        let height = &upper_left.im - &lower_right.im;
+        FixedComplex {
+            re: &upper_left.re + &width.scale(1, 2),
+            im: &upper_left.im - &height.scale(1, 2),
+        }
+    }
+
+    /// Like `rotate`, but in arbitrary precision, given the view's `center_exact`. The
+    /// offset from the center is turned in f64, which is plenty: it is never more than
+    /// the size of the view, so its rounding error is far smaller than a pixel.
+    pub fn rotate_exact(&self, point: FixedComplex, center: &FixedComplex) -> FixedComplex {
+        if self.rotation == 0.0 {
+            return point;
+        }
+        let offset = Complex {
+            re: (&point.re - &center.re).to_f64(),
+            im: (&point.im - &center.im).to_f64(),
+        };
+        let offset = FixedComplex::from_complex(self.rotate_offset(offset), center.re.bits());
+        center + &offset
+    }
+
+    /// The part of the view covered by `tile` of an image of the given `bounds`, as a view
+    /// of its own for an image the size of the tile. Its corners are those `pixel_to_point`
+    /// gives for the tile's corners, but in arbitrary precision, so a tile's points are
+    /// those of the whole view up to rounding in the last bit.
+    pub fn tile(&self, bounds: (usize, usize), tile: Tile) -> View {
+        // Extra bits keep the tile's edges as precise as f64 can use them.
+        let bits = self.bits() + 64;
+        let upper_left = self.upper_left_exact.with_bits(bits);
+        let lower_right = self.lower_right_exact.with_bits(bits);
+        let width = &lower_right.re - &upper_left.re;
+        let height = &upper_left.im - &lower_right.im;
+        let point = |x, y| FixedComplex {
+            re: &upper_left.re + &width.scale(x, bounds.0),
+            im: &upper_left.im - &height.scale(y, bounds.1),
+        };
+        let part = View::from_exact(
+            point(tile.left, tile.top),
+            point(tile.left + tile.width, tile.top + tile.height),
+        );
+        if self.rotation == 0.0 {
+            return part;
+        }
+        // The tile turns about the center of the whole view, not its own. Turning it about
+        // its own center gives the same points once the tile has been moved to where the
+        // whole view's turn takes that center.
+        let part_center = part.center_exact(bits);
+        let turned = self.rotate_exact(part_center.clone(), &self.center_exact(bits));
+        let shift = FixedComplex {
+            re: &turned.re - &part_center.re,
+            im: &turned.im - &part_center.im,
+        };
+        View {
+            rotation: self.rotation,
+            ..View::from_exact(
+                &part.upper_left_exact + &shift,
+                &part.lower_right_exact + &shift,
+            )
+        }
+    }
+}
+
+#[test]
+fn test_centered() {
+    let center = crate::parse_fixed_complex("-0.5,0.25").unwrap();
+    let view = View::centered(&center, 1.5, (400, 200));
+    assert_eq!(view.upper_left, Complex { re: -3.5, im: 1.75 });
+    assert_eq!(view.lower_right, Complex { re: 2.5, im: -1.25 });
+    assert_eq!(view.center(), Complex { re: -0.5, im: 0.25 });
+    assert_eq!(view.radius(), 1.5);
+    let view = View::centered(&center, 1.0, (100, 200));
+    assert_eq!(view.upper_left, Complex { re: -1.5, im: 2.25 });
+
+    // A tiny radius gets enough bits to keep the corners apart.
+    let view = View::centered(&center, 1e-40, (100, 100));
+    let width = &view.lower_right_exact.re - &view.upper_left_exact.re;
+    assert!((width.to_f64() - 2e-40).abs() < 1e-50);
+}
+
+#[test]
+fn test_square_pixels() {
+    let view = View::corners("-1.20,0.35", "-1,0.20").unwrap();
+    // Already square: untouched, down to the last bit.
+    assert_eq!(view.square_pixels((400, 300)), view);
+    // Too tall for the corners: the view grows vertically about its center.
+    let tall = view.square_pixels((400, 600));
+    assert!((tall.upper_left.im - 0.425).abs() < 1e-12);
+    assert!((tall.lower_right.im - 0.125).abs() < 1e-12);
+    assert_eq!(tall.upper_left.re, -1.2);
+    // Too wide: it grows horizontally.
+    let wide = view.square_pixels((800, 300));
+    assert!((wide.upper_left.re + 1.3).abs() < 1e-12);
+    assert!((wide.lower_right.re + 0.9).abs() < 1e-12);
+    assert!((wide.upper_left.im - 0.35).abs() < 1e-12);
+}
+
+#[test]
+fn test_rotate() {
+    let mut view = View::corners("-1,1", "1,-1").unwrap();
+    let point = Complex { re: 0.5, im: 0.0 };
+    assert_eq!(view.rotate(point), point);
+    view.rotation = std::f64::consts::FRAC_PI_2;
+    let turned = view.rotate(point);
+    assert!((turned - Complex { re: 0.0, im: 0.5 }).norm() < 1e-15);
+
+    let center = view.center_exact(80);
+    assert_eq!(center.to_complex(), Complex { re: 0.0, im: 0.0 });
+    let turned = view.rotate_exact(FixedComplex::from_complex(point, 80), &center);
+    assert!((turned.to_complex() - Complex { re: 0.0, im: 0.5 }).norm() < 1e-15);
+}
+
+#[test]
+fn test_tile() {
+    let mut view = View::corners("-1,1", "1,-1").unwrap();
+    let band = Tile {
+        left: 0,
+        top: 25,
+        width: 100,
+        height: 50,
+    };
+    let part = view.tile((100, 100), band);
+    assert_eq!(part.upper_left, Complex { re: -1.0, im: 0.5 });
+    assert_eq!(part.lower_right, Complex { re: 1.0, im: -0.5 });
+
+    let corner = Tile {
+        left: 60,
+        top: 10,
+        width: 20,
+        height: 30,
+    };
+    let part = view.tile((100, 100), corner);
+    let expected =
+        |x, y| crate::pixel_to_point((100, 100), (x, y), view.upper_left, view.lower_right);
+    assert!((part.upper_left - expected(60, 10)).norm() < 1e-15);
+    assert!((part.lower_right - expected(80, 40)).norm() < 1e-15);
+
+    // A turned tile's corners land where the whole view's turn takes them.
+    view.rotation = 0.3;
+    let part = view.tile((100, 100), corner);
+    let expected = view.rotate(expected(60, 10));
+    assert!((part.rotate(part.upper_left) - expected).norm() < 1e-15);
+}